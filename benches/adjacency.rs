@@ -0,0 +1,82 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use shipyard::*;
+use shipyard_relations::{
+    relation_mode::{DirectedExclusiveIncoming, RelationMode, SparseSet},
+    GetRelation, Relation, RelationViewMut,
+};
+
+const ENTITIES: usize = 10_000;
+const CHILDREN: usize = 4;
+
+struct Parent;
+
+impl Relation for Parent {
+    type Mode = DirectedExclusiveIncoming;
+}
+
+struct SparseParent;
+
+impl Relation for SparseParent {
+    type Mode = SparseSet<DirectedExclusiveIncoming>;
+}
+
+fn entities() -> (World, Vec<EntityId>) {
+    let mut world = World::new();
+    let entities = (0..ENTITIES).map(|_| world.add_entity(())).collect();
+
+    (world, entities)
+}
+
+fn build<R>(world: &World, entities: &[EntityId], relation: fn() -> R)
+where
+    R: Relation,
+{
+    let mut r_parent = world.borrow::<RelationViewMut<R>>().unwrap();
+
+    for (i, &child) in entities.iter().enumerate().skip(1) {
+        r_parent.insert(entities[(i - 1) / CHILDREN], child, relation());
+    }
+}
+
+fn bench_hierarchy<R>(c: &mut Criterion, name: &str, relation: fn() -> R)
+where
+    R: Relation,
+    for<'a> <R::Mode as RelationMode>::GetIncoming<'a, R>: IntoIterator,
+{
+    let mut group = c.benchmark_group(name);
+
+    group.bench_function("insert", |b| {
+        b.iter_batched(
+            entities,
+            |(world, entities)| build(&world, &entities, relation),
+            BatchSize::LargeInput,
+        )
+    });
+
+    let (world, entities) = entities();
+    build(&world, &entities, relation);
+    let r_parent = world.borrow::<RelationViewMut<R>>().unwrap();
+
+    group.bench_function("get_incoming", |b| {
+        b.iter(|| {
+            entities
+                .iter()
+                .filter(|&&e| r_parent.get_incoming(e).into_iter().next().is_some())
+                .count()
+        })
+    });
+
+    group.bench_function("visit_breadth_first", |b| {
+        b.iter(|| r_parent.visit_breadth_first(entities[0]).count())
+    });
+
+    group.finish();
+}
+
+fn hierarchy(c: &mut Criterion) {
+    bench_hierarchy(c, "graph_map", || Parent);
+    bench_hierarchy(c, "sparse_set", || SparseParent);
+}
+
+criterion_group!(benches, hierarchy);
+criterion_main!(benches);
@@ -0,0 +1,664 @@
+use std::{collections::HashSet, marker::PhantomData, slice};
+
+use petgraph::{
+    graphmap::{GraphMap, NeighborsDirected},
+    visit::{
+        GraphBase, GraphRef, IntoNeighbors, IntoNeighborsDirected, IntoNodeIdentifiers, Visitable,
+    },
+    EdgeType,
+};
+use shipyard::EntityId;
+
+use crate::{relation_mode::RelationMode, Relation};
+
+/// Adjacency selected by the mode of `R`.
+pub(crate) type AdjacencyOf<R> = <<R as Relation>::Mode as RelationMode>::Adjacency<R>;
+
+/// Backing storage for the relations of a relation type.
+///
+/// Modes use `GraphMap` by default, wrapping them in `SparseSet` switches to `SparseAdjacency`.
+pub trait Adjacency<R>: Default + Send + Sync + 'static
+where
+    R: Relation,
+{
+    type Nodes<'a>: Iterator<Item = EntityId> + 'a
+    where
+        Self: 'a;
+    type Neighbors<'a>: Iterator<Item = EntityId> + 'a
+    where
+        Self: 'a;
+    type Edges<'a>: Iterator<Item = (EntityId, &'a R)> + 'a
+    where
+        Self: 'a;
+    type EdgesMut<'a>: Iterator<Item = (EntityId, &'a mut R)> + 'a
+    where
+        Self: 'a;
+
+    /// Number of entities that have or had relations since they were last removed.
+    fn node_count(&self) -> usize;
+    fn nodes(&self) -> Self::Nodes<'_>;
+    fn contains_node(&self, entity: EntityId) -> bool;
+    fn contains_edge(&self, a: EntityId, b: EntityId) -> bool;
+    fn edge_weight(&self, a: EntityId, b: EntityId) -> Option<&R>;
+    fn edge_weight_mut(&mut self, a: EntityId, b: EntityId) -> Option<&mut R>;
+    /// Inserts a relation, returning the data it replaced.
+    fn add_edge(&mut self, a: EntityId, b: EntityId, relation: R) -> Option<R>;
+    fn remove_edge(&mut self, a: EntityId, b: EntityId) -> Option<R>;
+    /// Removes `entity` and all its relations.
+    fn remove_node(&mut self, entity: EntityId) -> bool;
+    /// Another generation of `entity` whose node would be replaced by a node for `entity`.
+    ///
+    /// Always `None` for `GraphMap`, which tells generations apart.
+    fn stale_node(&self, entity: EntityId) -> Option<EntityId>;
+    /// Entities related to `entity` in `direction`.
+    ///
+    /// Undirected relations yield all related entities, whatever the direction.
+    fn neighbors_directed(
+        &self,
+        entity: EntityId,
+        direction: petgraph::Direction,
+    ) -> Self::Neighbors<'_>;
+    /// Same as `neighbors_directed`, along with the data of each relation.
+    fn edges_directed(&self, entity: EntityId, direction: petgraph::Direction) -> Self::Edges<'_>;
+    /// Same as `neighbors_directed`, along with the mutable data of each relation.
+    ///
+    /// `GraphMap` can't borrow the data of several relations mutably without going through all of them,
+    /// this is `O(relations)` for it and `O(relations of entity)` for `SparseAdjacency`.
+    fn edges_directed_mut(
+        &mut self,
+        entity: EntityId,
+        direction: petgraph::Direction,
+    ) -> Self::EdgesMut<'_>;
+}
+
+impl<R, Ty> Adjacency<R> for GraphMap<EntityId, R, Ty>
+where
+    R: Relation,
+    Ty: EdgeType + Send + Sync + 'static,
+{
+    type Nodes<'a> = petgraph::graphmap::Nodes<'a, EntityId>;
+    type Neighbors<'a> = NeighborsDirected<'a, EntityId, Ty>;
+    type Edges<'a> = Box<dyn Iterator<Item = (EntityId, &'a R)> + 'a>;
+    type EdgesMut<'a> = Box<dyn Iterator<Item = (EntityId, &'a mut R)> + 'a>;
+
+    fn node_count(&self) -> usize {
+        GraphMap::node_count(self)
+    }
+
+    fn nodes(&self) -> Self::Nodes<'_> {
+        GraphMap::nodes(self)
+    }
+
+    fn contains_node(&self, entity: EntityId) -> bool {
+        GraphMap::contains_node(self, entity)
+    }
+
+    fn contains_edge(&self, a: EntityId, b: EntityId) -> bool {
+        GraphMap::contains_edge(self, a, b)
+    }
+
+    fn edge_weight(&self, a: EntityId, b: EntityId) -> Option<&R> {
+        GraphMap::edge_weight(self, a, b)
+    }
+
+    fn edge_weight_mut(&mut self, a: EntityId, b: EntityId) -> Option<&mut R> {
+        GraphMap::edge_weight_mut(self, a, b)
+    }
+
+    fn add_edge(&mut self, a: EntityId, b: EntityId, relation: R) -> Option<R> {
+        GraphMap::add_edge(self, a, b, relation)
+    }
+
+    fn remove_edge(&mut self, a: EntityId, b: EntityId) -> Option<R> {
+        GraphMap::remove_edge(self, a, b)
+    }
+
+    fn remove_node(&mut self, entity: EntityId) -> bool {
+        GraphMap::remove_node(self, entity)
+    }
+
+    fn stale_node(&self, _entity: EntityId) -> Option<EntityId> {
+        None
+    }
+
+    fn neighbors_directed(
+        &self,
+        entity: EntityId,
+        direction: petgraph::Direction,
+    ) -> Self::Neighbors<'_> {
+        GraphMap::neighbors_directed(self, entity, direction)
+    }
+
+    fn edges_directed(&self, entity: EntityId, direction: petgraph::Direction) -> Self::Edges<'_> {
+        Box::new(
+            GraphMap::edges_directed(self, entity, direction).map(
+                move |(a, b, r)| match direction {
+                    petgraph::Direction::Outgoing => (b, r),
+                    petgraph::Direction::Incoming => (a, r),
+                },
+            ),
+        )
+    }
+
+    // `GraphMap` has no way to borrow the weights of a single node's edges mutably,
+    // so this has to filter all edges.
+    fn edges_directed_mut(
+        &mut self,
+        entity: EntityId,
+        direction: petgraph::Direction,
+    ) -> Self::EdgesMut<'_> {
+        Box::new(self.all_edges_mut().filter_map(move |(a, b, r)| {
+            if Ty::is_directed() {
+                match direction {
+                    petgraph::Direction::Outgoing => (a == entity).then_some((b, r)),
+                    petgraph::Direction::Incoming => (b == entity).then_some((a, r)),
+                }
+            } else if a == entity {
+                Some((b, r))
+            } else if b == entity {
+                Some((a, r))
+            } else {
+                None
+            }
+        }))
+    }
+}
+
+#[derive(Clone)]
+struct Node {
+    entity: EntityId,
+    outgoing: Vec<usize>,
+    incoming: Vec<usize>,
+}
+
+#[derive(Clone)]
+struct Edge<R> {
+    a: EntityId,
+    b: EntityId,
+    relation: R,
+}
+
+/// Adjacency lists stored in a sparse set indexed by `EntityId::index`.
+///
+/// Entities are never hashed, relations are found by scanning the lists of one of their entities.
+/// This suits hierarchies and other relations where entities have few relations each.
+pub struct SparseAdjacency<R, Ty> {
+    nodes: Vec<Option<Node>>,
+    edges: Vec<Option<Edge<R>>>,
+    free: Vec<usize>,
+    node_count: usize,
+    _edge_type: PhantomData<Ty>,
+}
+
+impl<R, Ty> Clone for SparseAdjacency<R, Ty>
+where
+    R: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            nodes: self.nodes.clone(),
+            edges: self.edges.clone(),
+            free: self.free.clone(),
+            node_count: self.node_count,
+            _edge_type: PhantomData,
+        }
+    }
+}
+
+impl<R, Ty> Default for SparseAdjacency<R, Ty> {
+    fn default() -> Self {
+        Self {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            free: Vec::new(),
+            node_count: 0,
+            _edge_type: PhantomData,
+        }
+    }
+}
+
+impl<R, Ty> SparseAdjacency<R, Ty>
+where
+    R: Relation,
+    Ty: EdgeType + Send + Sync + 'static,
+{
+    fn node(&self, entity: EntityId) -> Option<&Node> {
+        self.nodes
+            .get(entity.index() as usize)?
+            .as_ref()
+            .filter(|node| node.entity == entity)
+    }
+
+    fn node_mut(&mut self, entity: EntityId) -> Option<&mut Node> {
+        self.nodes
+            .get_mut(entity.index() as usize)?
+            .as_mut()
+            .filter(|node| node.entity == entity)
+    }
+
+    fn node_or_insert(&mut self, entity: EntityId) -> &mut Node {
+        let index = entity.index() as usize;
+
+        if self.nodes.len() <= index {
+            self.nodes.resize_with(index + 1, || None);
+        }
+
+        // `RelationStorage` deletes the relations of an older generation with tracking first,
+        // this only happens when the adjacency is used on its own
+        if let Some(old) = self.stale_node(entity) {
+            self.remove_node(old);
+        }
+
+        let node_count = &mut self.node_count;
+        self.nodes[index].get_or_insert_with(|| {
+            *node_count += 1;
+            Node {
+                entity,
+                outgoing: Vec::new(),
+                incoming: Vec::new(),
+            }
+        })
+    }
+
+    fn find_edge(&self, a: EntityId, b: EntityId) -> Option<usize> {
+        let node = self.node(a)?;
+        let edge = |index: usize| self.edges[index].as_ref().unwrap();
+
+        node.outgoing
+            .iter()
+            .copied()
+            .find(|&index| edge(index).b == b)
+            .or_else(|| {
+                if Ty::is_directed() {
+                    None
+                } else {
+                    node.incoming
+                        .iter()
+                        .copied()
+                        .find(|&index| edge(index).a == b)
+                }
+            })
+    }
+
+    fn remove_edge_at(&mut self, index: usize) -> Option<R> {
+        let edge = self.edges[index].take()?;
+        self.free.push(index);
+
+        let unlink = |list: &mut Vec<usize>| {
+            if let Some(position) = list.iter().position(|&i| i == index) {
+                list.swap_remove(position);
+            }
+        };
+
+        if let Some(node) = self.node_mut(edge.a) {
+            unlink(&mut node.outgoing);
+        }
+        if let Some(node) = self.node_mut(edge.b) {
+            unlink(&mut node.incoming);
+        }
+
+        Some(edge.relation)
+    }
+
+    fn incident(&self, entity: EntityId, direction: petgraph::Direction) -> Incident<'_, R> {
+        let (outgoing, incoming): (&[usize], &[usize]) = match self.node(entity) {
+            None => (&[], &[]),
+            Some(node) if !Ty::is_directed() => (&node.outgoing, &node.incoming),
+            Some(node) => match direction {
+                petgraph::Direction::Outgoing => (&node.outgoing, &[]),
+                petgraph::Direction::Incoming => (&[], &node.incoming),
+            },
+        };
+
+        Incident {
+            edges: &self.edges,
+            outgoing: outgoing.iter(),
+            incoming: incoming.iter(),
+            skip_loops: !Ty::is_directed(),
+        }
+    }
+}
+
+impl<R, Ty> Adjacency<R> for SparseAdjacency<R, Ty>
+where
+    R: Relation,
+    Ty: EdgeType + Send + Sync + 'static,
+{
+    type Nodes<'a> = SparseNodes<'a>;
+    type Neighbors<'a> = SparseNeighbors<'a, R>;
+    type Edges<'a> = SparseEdges<'a, R>;
+    type EdgesMut<'a> = SparseEdgesMut<'a, R>;
+
+    fn node_count(&self) -> usize {
+        self.node_count
+    }
+
+    fn nodes(&self) -> Self::Nodes<'_> {
+        SparseNodes(self.nodes.iter())
+    }
+
+    fn contains_node(&self, entity: EntityId) -> bool {
+        self.node(entity).is_some()
+    }
+
+    fn contains_edge(&self, a: EntityId, b: EntityId) -> bool {
+        self.find_edge(a, b).is_some()
+    }
+
+    fn edge_weight(&self, a: EntityId, b: EntityId) -> Option<&R> {
+        let index = self.find_edge(a, b)?;
+
+        self.edges[index].as_ref().map(|edge| &edge.relation)
+    }
+
+    fn edge_weight_mut(&mut self, a: EntityId, b: EntityId) -> Option<&mut R> {
+        let index = self.find_edge(a, b)?;
+
+        self.edges[index].as_mut().map(|edge| &mut edge.relation)
+    }
+
+    fn add_edge(&mut self, a: EntityId, b: EntityId, relation: R) -> Option<R> {
+        if let Some(old) = self.edge_weight_mut(a, b) {
+            return Some(std::mem::replace(old, relation));
+        }
+
+        self.node_or_insert(a);
+        self.node_or_insert(b);
+
+        let edge = Some(Edge { a, b, relation });
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.edges[index] = edge;
+                index
+            }
+            None => {
+                self.edges.push(edge);
+                self.edges.len() - 1
+            }
+        };
+
+        self.node_or_insert(a).outgoing.push(index);
+        self.node_or_insert(b).incoming.push(index);
+
+        None
+    }
+
+    fn remove_edge(&mut self, a: EntityId, b: EntityId) -> Option<R> {
+        let index = self.find_edge(a, b)?;
+
+        self.remove_edge_at(index)
+    }
+
+    fn remove_node(&mut self, entity: EntityId) -> bool {
+        let Some(node) = self.node(entity) else {
+            return false;
+        };
+
+        let edges = node
+            .outgoing
+            .iter()
+            .chain(&node.incoming)
+            .copied()
+            .collect::<Vec<_>>();
+
+        // self-loops are listed twice, the second removal does nothing
+        for index in edges {
+            self.remove_edge_at(index);
+        }
+
+        self.nodes[entity.index() as usize] = None;
+        self.node_count -= 1;
+
+        true
+    }
+
+    fn stale_node(&self, entity: EntityId) -> Option<EntityId> {
+        self.nodes
+            .get(entity.index() as usize)?
+            .as_ref()
+            .map(|node| node.entity)
+            .filter(|&old| old != entity)
+    }
+
+    fn neighbors_directed(
+        &self,
+        entity: EntityId,
+        direction: petgraph::Direction,
+    ) -> Self::Neighbors<'_> {
+        SparseNeighbors(self.incident(entity, direction))
+    }
+
+    fn edges_directed(&self, entity: EntityId, direction: petgraph::Direction) -> Self::Edges<'_> {
+        SparseEdges(self.incident(entity, direction))
+    }
+
+    fn edges_directed_mut(
+        &mut self,
+        entity: EntityId,
+        direction: petgraph::Direction,
+    ) -> Self::EdgesMut<'_> {
+        let mut indices = self.incident(entity, direction).collect::<Vec<_>>();
+
+        // splitting the edges in order gives access to each of them mutably
+        indices.sort_unstable_by_key(|&(_, index)| index);
+
+        SparseEdgesMut {
+            edges: &mut self.edges,
+            offset: 0,
+            indices: indices.into_iter(),
+        }
+    }
+}
+
+/// Relations of an entity, with the entity on the other side.
+struct Incident<'a, R> {
+    edges: &'a [Option<Edge<R>>],
+    outgoing: slice::Iter<'a, usize>,
+    incoming: slice::Iter<'a, usize>,
+    skip_loops: bool,
+}
+
+impl<R> Iterator for Incident<'_, R> {
+    type Item = (EntityId, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(&index) = self.outgoing.next() {
+            return Some((self.edges[index].as_ref()?.b, index));
+        }
+
+        loop {
+            let &index = self.incoming.next()?;
+            let edge = self.edges[index].as_ref()?;
+
+            // undirected self-loops were already yielded with the outgoing relations
+            if !(self.skip_loops && edge.a == edge.b) {
+                return Some((edge.a, index));
+            }
+        }
+    }
+}
+
+pub struct SparseNodes<'a>(slice::Iter<'a, Option<Node>>);
+
+impl Iterator for SparseNodes<'_> {
+    type Item = EntityId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0
+            .find_map(|node| node.as_ref().map(|node| node.entity))
+    }
+}
+
+pub struct SparseNeighbors<'a, R>(Incident<'a, R>);
+
+impl<R> Iterator for SparseNeighbors<'_, R> {
+    type Item = EntityId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(other, _)| other)
+    }
+}
+
+pub struct SparseEdges<'a, R>(Incident<'a, R>);
+
+impl<'a, R> Iterator for SparseEdges<'a, R> {
+    type Item = (EntityId, &'a R);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (other, index) = self.0.next()?;
+        let edges = self.0.edges;
+
+        Some((other, &edges[index].as_ref()?.relation))
+    }
+}
+
+pub struct SparseEdgesMut<'a, R> {
+    edges: &'a mut [Option<Edge<R>>],
+    offset: usize,
+    indices: std::vec::IntoIter<(EntityId, usize)>,
+}
+
+impl<'a, R> Iterator for SparseEdgesMut<'a, R> {
+    type Item = (EntityId, &'a mut R);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (other, index) = self.indices.next()?;
+
+        let edges = std::mem::take(&mut self.edges);
+        let (edge, rest) = edges[index - self.offset..].split_first_mut()?;
+        self.edges = rest;
+        self.offset = index + 1;
+
+        Some((other, &mut edge.as_mut()?.relation))
+    }
+}
+
+/// Lets petgraph's traversals and algorithms run on any `Adjacency`.
+///
+/// Neighbors are visited in `EntityId` order when `sorted` is set.
+pub(crate) struct Walk<'a, R, A> {
+    adjacency: &'a A,
+    sorted: bool,
+    _relation: PhantomData<fn() -> R>,
+}
+
+impl<'a, R, A> Walk<'a, R, A> {
+    pub(crate) fn new(adjacency: &'a A, sorted: bool) -> Self {
+        Walk {
+            adjacency,
+            sorted,
+            _relation: PhantomData,
+        }
+    }
+}
+
+impl<R, A> Clone for Walk<'_, R, A> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<R, A> Copy for Walk<'_, R, A> {}
+
+impl<R, A> GraphBase for Walk<'_, R, A> {
+    type NodeId = EntityId;
+    type EdgeId = (EntityId, EntityId);
+}
+
+impl<R, A> GraphRef for Walk<'_, R, A> {}
+
+impl<'a, R, A> IntoNeighbors for Walk<'a, R, A>
+where
+    R: Relation,
+    A: Adjacency<R>,
+{
+    type Neighbors = WalkNeighbors<A::Neighbors<'a>>;
+
+    fn neighbors(self, entity: EntityId) -> Self::Neighbors {
+        self.neighbors_directed(entity, petgraph::Direction::Outgoing)
+    }
+}
+
+impl<'a, R, A> IntoNeighborsDirected for Walk<'a, R, A>
+where
+    R: Relation,
+    A: Adjacency<R>,
+{
+    type NeighborsDirected = WalkNeighbors<A::Neighbors<'a>>;
+
+    fn neighbors_directed(
+        self,
+        entity: EntityId,
+        direction: petgraph::Direction,
+    ) -> Self::NeighborsDirected {
+        WalkNeighbors::new(
+            self.adjacency.neighbors_directed(entity, direction),
+            self.sorted,
+        )
+    }
+}
+
+impl<'a, R, A> IntoNodeIdentifiers for Walk<'a, R, A>
+where
+    R: Relation,
+    A: Adjacency<R>,
+{
+    type NodeIdentifiers = WalkNeighbors<A::Nodes<'a>>;
+
+    fn node_identifiers(self) -> Self::NodeIdentifiers {
+        WalkNeighbors::new(self.adjacency.nodes(), self.sorted)
+    }
+}
+
+impl<R, A> Visitable for Walk<'_, R, A>
+where
+    R: Relation,
+    A: Adjacency<R>,
+{
+    type Map = HashSet<EntityId>;
+
+    fn visit_map(&self) -> Self::Map {
+        HashSet::with_capacity(self.adjacency.node_count())
+    }
+
+    fn reset_map(&self, map: &mut Self::Map) {
+        map.clear();
+    }
+}
+
+/// Entities yielded by the petgraph traits implemented on relation views.
+///
+/// Sorted by `EntityId` for deterministic relations.
+pub enum WalkNeighbors<I> {
+    Sorted(std::vec::IntoIter<EntityId>),
+    Unsorted(I),
+}
+
+impl<I> WalkNeighbors<I>
+where
+    I: Iterator<Item = EntityId>,
+{
+    pub(crate) fn new(entities: I, sorted: bool) -> Self {
+        if sorted {
+            let mut entities = entities.collect::<Vec<_>>();
+            entities.sort_unstable();
+            WalkNeighbors::Sorted(entities.into_iter())
+        } else {
+            WalkNeighbors::Unsorted(entities)
+        }
+    }
+}
+
+impl<I> Iterator for WalkNeighbors<I>
+where
+    I: Iterator<Item = EntityId>,
+{
+    type Item = EntityId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            WalkNeighbors::Sorted(iter) => iter.next(),
+            WalkNeighbors::Unsorted(iter) => iter.next(),
+        }
+    }
+}
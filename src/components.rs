@@ -0,0 +1,162 @@
+use std::marker::PhantomData;
+
+use shipyard::*;
+
+use crate::{
+    adjacency::Adjacency, relation_mode::RelationMode, storage::RelationStorage, Relation,
+};
+
+/// Marker component of entities with at least one outgoing relation `R`, see `Relation::MARKERS`.
+///
+/// Undirected relations count as both outgoing and incoming.
+///
+/// Stale after deleting an entity it was related to from the `World`,
+/// until `sync_relation_components::<R>` runs or a `RelationViewMut<R>` is borrowed.
+#[derive(Component)]
+pub struct HasOutgoing<R: Relation>(PhantomData<R>);
+
+/// Marker component of entities with at least one incoming relation `R`, see `Relation::MARKERS`.
+///
+/// Undirected relations count as both outgoing and incoming.
+///
+/// Stale after deleting an entity it was related to from the `World`,
+/// until `sync_relation_components::<R>` runs or a `RelationViewMut<R>` is borrowed.
+#[derive(Component)]
+pub struct HasIncoming<R: Relation>(PhantomData<R>);
+
+/// The entity on the other side of the outgoing relation `R` of an entity, see `Relation::MIRROR`.
+///
+/// Only kept for modes exclusive on the outgoing side.
+///
+/// After the entity on the other side is deleted from the `World`, it points to a dead entity
+/// until `sync_relation_components::<R>` runs or a `RelationViewMut<R>` is borrowed, which remove it.
+#[derive(Component)]
+pub struct Target<R: Relation>(pub EntityId, PhantomData<R>);
+
+/// The entity on the other side of the incoming relation `R` of an entity, see `Relation::MIRROR`.
+///
+/// Only kept for modes exclusive on the incoming side.
+///
+/// After the entity on the other side is deleted from the `World`, it points to a dead entity
+/// until `sync_relation_components::<R>` runs or a `RelationViewMut<R>` is borrowed, which remove it.
+#[derive(Component)]
+pub struct Source<R: Relation>(pub EntityId, PhantomData<R>);
+
+/// Views over the components kept in sync with the relations `R`.
+///
+/// Only the components enabled by `R` are borrowed.
+pub(crate) struct RelationComponents<'a, R>
+where
+    R: Relation,
+{
+    has_outgoing: Option<ViewMut<'a, HasOutgoing<R>>>,
+    has_incoming: Option<ViewMut<'a, HasIncoming<R>>>,
+    target: Option<ViewMut<'a, Target<R>>>,
+    source: Option<ViewMut<'a, Source<R>>>,
+}
+
+impl<'a, R> RelationComponents<'a, R>
+where
+    R: Relation,
+{
+    pub(crate) fn borrow(
+        all_storages: &'a AllStorages,
+        all_borrow: Option<SharedBorrow<'a>>,
+        last_run: Option<TrackingTimestamp>,
+        current: TrackingTimestamp,
+    ) -> Result<Self, error::GetStorage> {
+        macro_rules! borrow_if {
+            ($enabled: expr, $component: ty) => {
+                if $enabled {
+                    Some(<ViewMut<'_, $component> as Borrow>::borrow(
+                        all_storages,
+                        all_borrow.clone(),
+                        last_run,
+                        current,
+                    )?)
+                } else {
+                    None
+                }
+            };
+        }
+
+        Ok(RelationComponents {
+            has_outgoing: borrow_if!(R::MARKERS, HasOutgoing<R>),
+            has_incoming: borrow_if!(R::MARKERS, HasIncoming<R>),
+            target: borrow_if!(Self::mirrors_target(), Target<R>),
+            source: borrow_if!(Self::mirrors_source(), Source<R>),
+        })
+    }
+
+    pub(crate) fn borrow_info(info: &mut Vec<info::TypeInfo>) {
+        if R::MARKERS {
+            <ViewMut<'_, HasOutgoing<R>> as BorrowInfo>::borrow_info(info);
+            <ViewMut<'_, HasIncoming<R>> as BorrowInfo>::borrow_info(info);
+        }
+        if Self::mirrors_target() {
+            <ViewMut<'_, Target<R>> as BorrowInfo>::borrow_info(info);
+        }
+        if Self::mirrors_source() {
+            <ViewMut<'_, Source<R>> as BorrowInfo>::borrow_info(info);
+        }
+    }
+
+    fn mirrors_target() -> bool {
+        R::MIRROR && R::Mode::is_exclusive_outgoing()
+    }
+
+    fn mirrors_source() -> bool {
+        R::MIRROR && R::Mode::is_exclusive_incoming()
+    }
+
+    /// Updates the components of the entities whose relations changed since the last sync.
+    pub(crate) fn sync(&mut self, storage: &mut RelationStorage<R>) {
+        let mut outdated = std::mem::take(&mut storage.outdated);
+        outdated.sort_unstable();
+        outdated.dedup();
+
+        for entity in outdated {
+            if let Some(has_outgoing) = &mut self.has_outgoing {
+                if storage.has_neighbor(entity, petgraph::Direction::Outgoing) {
+                    has_outgoing.add_component_unchecked(entity, HasOutgoing(PhantomData));
+                } else {
+                    has_outgoing.remove(entity);
+                }
+            }
+
+            if let Some(has_incoming) = &mut self.has_incoming {
+                if storage.has_neighbor(entity, petgraph::Direction::Incoming) {
+                    has_incoming.add_component_unchecked(entity, HasIncoming(PhantomData));
+                } else {
+                    has_incoming.remove(entity);
+                }
+            }
+
+            if let Some(target) = &mut self.target {
+                match storage
+                    .graph
+                    .neighbors_directed(entity, petgraph::Direction::Outgoing)
+                    .next()
+                {
+                    Some(b) => target.add_component_unchecked(entity, Target(b, PhantomData)),
+                    None => {
+                        target.remove(entity);
+                    }
+                }
+            }
+
+            if let Some(source) = &mut self.source {
+                match storage
+                    .graph
+                    .neighbors_directed(entity, petgraph::Direction::Incoming)
+                    .next()
+                {
+                    Some(a) => source.add_component_unchecked(entity, Source(a, PhantomData)),
+                    None => {
+                        source.remove(entity);
+                    }
+                }
+            }
+        }
+    }
+}
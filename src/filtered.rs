@@ -0,0 +1,118 @@
+use petgraph::{algo::has_path_connecting, visit::NodeFiltered};
+use shipyard::*;
+
+use crate::{
+    adjacency::{Adjacency, Walk},
+    iter::{FilteredBreadthFirstIter, FilteredDepthFirstIter, FilteredWalk},
+    storage::RelationStorage,
+    Relation,
+};
+
+/// Relations `R` restricted to the entities accepted by a filter, see `GetRelation::filtered`.
+///
+/// Nothing is copied, the filter is evaluated as entities are reached.
+/// Relations are only seen when both of their entities are accepted.
+///
+/// To run petgraph algorithms on a subset of entities,
+/// wrap a view reference in `petgraph::visit::NodeFiltered` instead.
+pub struct FilteredRelation<'a, R>
+where
+    R: Relation,
+{
+    storage: &'a RelationStorage<R>,
+    filter: Box<dyn Fn(EntityId) -> bool + 'a>,
+}
+
+impl<'a, R> FilteredRelation<'a, R>
+where
+    R: Relation,
+{
+    pub(crate) fn new<F>(storage: &'a RelationStorage<R>, filter: F) -> Self
+    where
+        F: Fn(EntityId) -> bool + 'a,
+    {
+        FilteredRelation {
+            storage,
+            filter: Box::new(filter),
+        }
+    }
+
+    fn walk(&self) -> FilteredWalk<'_, R> {
+        NodeFiltered(
+            Walk::new(&self.storage.graph, R::DETERMINISTIC),
+            &*self.filter,
+        )
+    }
+
+    /// Returns `true` if `entity` is accepted by the filter.
+    pub fn contains(&self, entity: EntityId) -> bool {
+        (self.filter)(entity)
+    }
+
+    pub fn relation(&self, a: EntityId, b: EntityId) -> Option<&'a R> {
+        if self.contains(a) && self.contains(b) {
+            self.storage.graph.edge_weight(a, b)
+        } else {
+            None
+        }
+    }
+
+    /// Iterates the outgoing relations of `entity` whatever the mode of `R`.
+    pub fn get(&self, entity: EntityId) -> Box<dyn Iterator<Item = (EntityId, &'a R)> + '_> {
+        self.get_outgoing(entity)
+    }
+
+    pub fn get_outgoing(
+        &self,
+        entity: EntityId,
+    ) -> Box<dyn Iterator<Item = (EntityId, &'a R)> + '_> {
+        self.edges(entity, petgraph::Direction::Outgoing)
+    }
+
+    pub fn get_incoming(
+        &self,
+        entity: EntityId,
+    ) -> Box<dyn Iterator<Item = (EntityId, &'a R)> + '_> {
+        self.edges(entity, petgraph::Direction::Incoming)
+    }
+
+    fn edges(
+        &self,
+        entity: EntityId,
+        direction: petgraph::Direction,
+    ) -> Box<dyn Iterator<Item = (EntityId, &'a R)> + '_> {
+        if !self.contains(entity) {
+            return Box::new(std::iter::empty());
+        }
+
+        Box::new(
+            self.storage
+                .edges(entity, direction)
+                .into_iter()
+                .filter(|(other, _)| self.contains(*other)),
+        )
+    }
+
+    pub fn visit_depth_first(&self, entity: EntityId) -> FilteredDepthFirstIter<'_, R> {
+        FilteredDepthFirstIter::new(self.walk(), entity)
+    }
+
+    pub fn visit_breadth_first(&self, entity: EntityId) -> FilteredBreadthFirstIter<'_, R> {
+        FilteredBreadthFirstIter::new(self.walk(), entity)
+    }
+
+    /// Returns `true` if `b` can be reached from `a` only going through accepted entities.
+    pub fn is_reachable(&self, a: EntityId, b: EntityId) -> bool {
+        self.contains(a) && self.contains(b) && has_path_connecting(&self.walk(), a, b, None)
+    }
+
+    /// Shortest path of accepted entities from `a` to `b`, both included.
+    pub fn path(&self, a: EntityId, b: EntityId) -> Option<Vec<EntityId>> {
+        if !self.contains(a) || !self.contains(b) {
+            return None;
+        }
+
+        self.storage
+            .path_through(a, b, |_, next| (self.filter)(next))
+    }
+}
@@ -1,12 +1,17 @@
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
 
 use petgraph::{
-    prelude::GraphMap,
-    visit::{Bfs, Dfs},
+    visit::{Bfs, Dfs, NodeFiltered},
+    EdgeType,
 };
 use shipyard::*;
 
-use crate::{relation_mode::RelationMode, Relation};
+use crate::{
+    adjacency::{Adjacency, AdjacencyOf, Walk},
+    relation_mode::RelationMode,
+    storage::RelationStorage,
+    Relation,
+};
 
 /// Provides iterators for traversing relations depth or breadth first.
 pub trait RelationsIter<R>
@@ -21,7 +26,7 @@ pub struct DepthFirstIter<'a, R>
 where
     R: Relation,
 {
-    graph: &'a GraphMap<EntityId, R, <R::Mode as RelationMode>::EdgeType>,
+    walk: Walk<'a, R, AdjacencyOf<R>>,
     dfs: Dfs<EntityId, HashSet<EntityId>>,
 }
 
@@ -32,7 +37,7 @@ where
     type Item = EntityId;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.dfs.next(self.graph)
+        self.dfs.next(self.walk)
     }
 }
 
@@ -40,13 +45,12 @@ impl<'a, R> DepthFirstIter<'a, R>
 where
     R: Relation,
 {
-    pub fn new(
-        graph: &'a GraphMap<EntityId, R, <R::Mode as RelationMode>::EdgeType>,
-        start: EntityId,
-    ) -> Self {
+    pub fn new(graph: &'a AdjacencyOf<R>, start: EntityId) -> Self {
+        let walk = Walk::new(graph, R::DETERMINISTIC);
+
         DepthFirstIter {
-            graph,
-            dfs: Dfs::new(graph, start),
+            walk,
+            dfs: Dfs::new(walk, start),
         }
     }
 }
@@ -55,7 +59,7 @@ pub struct BreadthFirstIter<'a, R>
 where
     R: Relation,
 {
-    graph: &'a GraphMap<EntityId, R, <R::Mode as RelationMode>::EdgeType>,
+    walk: Walk<'a, R, AdjacencyOf<R>>,
     bfs: Bfs<EntityId, HashSet<EntityId>>,
 }
 
@@ -66,7 +70,7 @@ where
     type Item = EntityId;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.bfs.next(self.graph)
+        self.bfs.next(self.walk)
     }
 }
 
@@ -74,13 +78,166 @@ impl<'a, R> BreadthFirstIter<'a, R>
 where
     R: Relation,
 {
-    pub fn new(
-        graph: &'a GraphMap<EntityId, R, <R::Mode as RelationMode>::EdgeType>,
-        start: EntityId,
-    ) -> Self {
+    pub fn new(graph: &'a AdjacencyOf<R>, start: EntityId) -> Self {
+        let walk = Walk::new(graph, R::DETERMINISTIC);
+
         BreadthFirstIter {
+            walk,
+            bfs: Bfs::new(walk, start),
+        }
+    }
+}
+
+/// Iterates the ancestors or descendants of an entity, closest first, see `GetRelation::ancestors`.
+///
+/// The entity itself isn't yielded.
+pub struct LineageIter<'a, R>
+where
+    R: Relation,
+{
+    graph: &'a AdjacencyOf<R>,
+    direction: petgraph::Direction,
+    /// Every entity has at most one neighbor in `direction`.
+    chain: bool,
+    queue: VecDeque<EntityId>,
+    visited: HashSet<EntityId>,
+}
+
+impl<R> Iterator for LineageIter<'_, R>
+where
+    R: Relation,
+{
+    type Item = EntityId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.queue.pop_front()?;
+
+        if self.chain {
+            let next = self.graph.neighbors_directed(node, self.direction).next();
+
+            if let Some(next) = next {
+                // acyclic chains can't loop back, only self-loops have to be checked
+                if next != node && (RelationStorage::<R>::is_acyclic() || self.visited.insert(next))
+                {
+                    self.queue.push_back(next);
+                }
+            }
+        } else {
+            let mut neighbors = self
+                .graph
+                .neighbors_directed(node, self.direction)
+                .collect::<Vec<_>>();
+
+            if R::DETERMINISTIC {
+                neighbors.sort_unstable();
+            }
+
+            for neighbor in neighbors {
+                if self.visited.insert(neighbor) {
+                    self.queue.push_back(neighbor);
+                }
+            }
+        }
+
+        Some(node)
+    }
+}
+
+impl<'a, R> LineageIter<'a, R>
+where
+    R: Relation,
+{
+    pub fn new(graph: &'a AdjacencyOf<R>, start: EntityId, direction: petgraph::Direction) -> Self {
+        let chain = <R::Mode as RelationMode>::EdgeType::is_directed()
+            && match direction {
+                petgraph::Direction::Outgoing => R::Mode::is_exclusive_outgoing(),
+                petgraph::Direction::Incoming => R::Mode::is_exclusive_incoming(),
+            };
+
+        let mut iter = LineageIter {
             graph,
-            bfs: Bfs::new(graph, start),
+            direction,
+            chain,
+            queue: VecDeque::from([start]),
+            visited: HashSet::from([start]),
+        };
+
+        // skips `start`
+        iter.next();
+
+        iter
+    }
+}
+
+/// `Walk` only seeing the entities accepted by the filter of a `FilteredRelation`.
+pub(crate) type FilteredWalk<'a, R> =
+    NodeFiltered<Walk<'a, R, AdjacencyOf<R>>, &'a (dyn Fn(EntityId) -> bool + 'a)>;
+
+/// Same as `DepthFirstIter`, only going through entities accepted by a `FilteredRelation`.
+pub struct FilteredDepthFirstIter<'a, R>
+where
+    R: Relation,
+{
+    walk: FilteredWalk<'a, R>,
+    dfs: Dfs<EntityId, HashSet<EntityId>>,
+}
+
+impl<R> Iterator for FilteredDepthFirstIter<'_, R>
+where
+    R: Relation,
+{
+    type Item = EntityId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.dfs.next(&self.walk)
+    }
+}
+
+impl<'a, R> FilteredDepthFirstIter<'a, R>
+where
+    R: Relation,
+{
+    pub(crate) fn new(walk: FilteredWalk<'a, R>, start: EntityId) -> Self {
+        let dfs = if (walk.1)(start) {
+            Dfs::new(&walk, start)
+        } else {
+            Dfs::empty(&walk)
+        };
+
+        FilteredDepthFirstIter { walk, dfs }
+    }
+}
+
+/// Same as `BreadthFirstIter`, only going through entities accepted by a `FilteredRelation`.
+pub struct FilteredBreadthFirstIter<'a, R>
+where
+    R: Relation,
+{
+    walk: FilteredWalk<'a, R>,
+    bfs: Bfs<EntityId, HashSet<EntityId>>,
+}
+
+impl<R> Iterator for FilteredBreadthFirstIter<'_, R>
+where
+    R: Relation,
+{
+    type Item = EntityId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.bfs.next(&self.walk)
+    }
+}
+
+impl<'a, R> FilteredBreadthFirstIter<'a, R>
+where
+    R: Relation,
+{
+    pub(crate) fn new(walk: FilteredWalk<'a, R>, start: EntityId) -> Self {
+        let mut bfs = Bfs::new(&walk, start);
+        if !(walk.1)(start) {
+            bfs.stack.clear();
         }
+
+        FilteredBreadthFirstIter { walk, bfs }
     }
 }
@@ -1,23 +1,32 @@
 //! Shipyard Relations provides Entity Relations for [Shipyard](https://github.com/leudz/shipyard).
 
+/// Backing storages for the relations of a relation type.
+pub mod adjacency;
+mod components;
+mod filtered;
 /// Iterator types and trait.
 pub mod iter;
 mod relation;
+mod relation_bundle;
 mod relation_ext;
 /// Modes to control directedness and exclusiveness of a relation type.
 pub mod relation_mode;
 mod storage;
 mod view;
 mod view_mut;
+mod visit;
 
 #[doc(inline)]
 pub use self::iter::RelationsIter;
 pub use self::{
-    relation::{GetRelation, Relation},
+    components::{HasIncoming, HasOutgoing, Source, Target},
+    filtered::FilteredRelation,
+    relation::{GetRelation, Relation, RelationTracking, Retention, SelfLoops, TrackingCursor},
+    relation_bundle::{RelationBundle, RelationFrom, RelationTo},
     relation_ext::RelationExt,
     storage::RelationStorage,
     view::RelationView,
-    view_mut::{InsertError, RelationViewMut},
+    view_mut::{sync_relation_components, CopyDirection, InsertError, RelationViewMut},
 };
 #[doc(hidden)]
 pub use petgraph::prelude::GraphMap;
@@ -116,7 +125,7 @@ fn test_undirected_cyclic() {
 
     assert_eq!(
         world.borrow::<UniqueView<Deleted>>().unwrap().0,
-        vec![(e0, e2), (e3, e2)]
+        vec![(e0, e2), (e2, e3)]
     );
 
     world.run_workload(workload).unwrap();
@@ -238,3 +247,1905 @@ fn test_directed_exclusive() {
         vec![e1, e2]
     );
 }
+
+#[test]
+fn test_add_entity_with_relations() {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use shipyard::*;
+
+    use crate::{
+        relation_mode::DirectedExclusiveOutgoing, GetRelation, InsertError, Relation, RelationExt,
+        RelationFrom, RelationTo, RelationView, RelationViewMut,
+    };
+
+    #[derive(Debug)]
+    struct ChildOf;
+
+    impl Relation for ChildOf {
+        type Mode = DirectedExclusiveOutgoing;
+    }
+
+    let mut world = World::new();
+
+    let root = world.add_entity(());
+    let child = world
+        .add_entity_with_relations((), RelationTo(root, ChildOf))
+        .unwrap();
+    let grandchild = world.add_entity(());
+    let middle = world
+        .add_entity_with_relations(
+            (),
+            (
+                RelationTo(child, ChildOf),
+                RelationFrom(grandchild, ChildOf),
+            ),
+        )
+        .unwrap();
+
+    let r_child_of = world.borrow::<RelationView<ChildOf>>().unwrap();
+
+    assert_eq!(r_child_of.get(child).map(|e| e.0), Some(root));
+    assert_eq!(r_child_of.get(middle).map(|e| e.0), Some(child));
+    assert_eq!(r_child_of.get(grandchild).map(|e| e.0), Some(middle));
+
+    drop(r_child_of);
+
+    match world.add_entity_with_relations(
+        (),
+        (RelationTo(grandchild, ChildOf), RelationFrom(root, ChildOf)),
+    ) {
+        Err(InsertError::CycleDetected { a, b, path }) => {
+            assert_eq!(a, root);
+            assert_eq!(path, vec![b, grandchild, middle, child, root]);
+        }
+        result => panic!("expected a cycle, got {result:?}"),
+    }
+
+    // the cycle only appears once `child` is taken from `root`
+    assert!(matches!(
+        world.add_entity_with_relations(
+            (),
+            (
+                RelationFrom(child, ChildOf),
+                RelationTo(grandchild, ChildOf)
+            ),
+        ),
+        Err(InsertError::CycleDetected { .. })
+    ));
+
+    let r_child_of = world.borrow::<RelationView<ChildOf>>().unwrap();
+
+    assert_eq!(r_child_of.get(child).map(|e| e.0), Some(root));
+    assert_eq!(r_child_of.deleted().count(), 0);
+
+    drop(r_child_of);
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let on_insert_calls = calls.clone();
+    world
+        .borrow::<RelationViewMut<ChildOf>>()
+        .unwrap()
+        .on_insert(move |_, b, _| {
+            on_insert_calls.fetch_add(1, Ordering::Relaxed);
+            b != root
+        });
+
+    // taking `grandchild` from `middle` would be accepted, but nothing is inserted
+    assert_eq!(
+        world.add_entity_with_relations(
+            (),
+            (RelationFrom(grandchild, ChildOf), RelationTo(root, ChildOf)),
+        ),
+        Err(InsertError::Rejected)
+    );
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+    let r_child_of = world.borrow::<RelationView<ChildOf>>().unwrap();
+
+    assert_eq!(r_child_of.get(grandchild).map(|e| e.0), Some(middle));
+    assert_eq!(r_child_of.get_incoming(middle).count(), 1);
+    assert_eq!(r_child_of.deleted().count(), 0);
+
+    drop(r_child_of);
+
+    world
+        .add_entity_with_relations(
+            (),
+            (
+                RelationTo(child, ChildOf),
+                RelationFrom(grandchild, ChildOf),
+            ),
+        )
+        .unwrap();
+    assert_eq!(calls.load(Ordering::Relaxed), 4);
+}
+
+#[test]
+fn test_copy_relations() {
+    use shipyard::*;
+
+    use crate::{
+        relation_mode::{Directed, DirectedExclusiveOutgoing},
+        CopyDirection, InsertError, Relation, RelationViewMut,
+    };
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Foo(u32);
+
+    impl Relation for Foo {
+        type Mode = Directed;
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Bar(u32);
+
+    impl Relation for Bar {
+        type Mode = DirectedExclusiveOutgoing;
+    }
+
+    let mut world = World::new();
+
+    let e0 = world.add_entity(());
+    let e1 = world.add_entity(());
+    let e2 = world.add_entity(());
+    let e3 = world.add_entity(());
+    let e4 = world.add_entity(());
+
+    world.run(
+        |mut entities: EntitiesViewMut, mut r_foo: RelationViewMut<Foo>| {
+            r_foo.insert(e0, e1, Foo(0));
+            r_foo.insert(e1, e2, Foo(1));
+            r_foo.insert(e1, e3, Foo(2));
+
+            r_foo
+                .copy_relations(e1, e4, CopyDirection::Incoming)
+                .unwrap();
+
+            assert_eq!(r_foo.relation(e0, e4), Some(&Foo(0)));
+            assert_eq!(r_foo.get(e4).count(), 0);
+
+            let mapping = r_foo.clone_subtree(&mut entities, e1).unwrap();
+
+            assert_eq!(mapping.len(), 3);
+            assert_eq!(r_foo.relation(e0, mapping[&e1]), Some(&Foo(0)));
+            assert_eq!(r_foo.relation(mapping[&e1], mapping[&e2]), Some(&Foo(1)));
+            assert_eq!(r_foo.relation(mapping[&e1], mapping[&e3]), Some(&Foo(2)));
+            assert_eq!(r_foo.get(e1).count(), 2);
+
+            // the copy of `e1 -> e3` is rejected, the copy of `e0 -> e1` is taken back
+            r_foo.on_insert(|_, _, relation| relation.0 != 2);
+
+            assert_eq!(
+                r_foo.clone_subtree(&mut entities, e1),
+                Err(InsertError::Rejected)
+            );
+            assert_eq!(r_foo.get(e0).count(), 3);
+            assert_eq!(r_foo.get(e1).count(), 2);
+
+            // the copy of `e1 -> e2` is taken back too
+            assert_eq!(
+                r_foo.copy_relations(e1, e4, CopyDirection::Outgoing),
+                Err(InsertError::Rejected)
+            );
+            assert_eq!(r_foo.get(e4).count(), 0);
+        },
+    );
+
+    world.run(
+        |mut entities: EntitiesViewMut, mut r_bar: RelationViewMut<Bar>| {
+            r_bar.insert(e0, e1, Bar(0));
+            r_bar.insert(e1, e2, Bar(1));
+
+            let mapping = r_bar.clone_subtree(&mut entities, e1).unwrap();
+
+            // `e0` can only relate to one entity, copying its relation would take it from `e1`
+            assert_eq!(r_bar.relation(e0, e1), Some(&Bar(0)));
+            assert_eq!(r_bar.relation(e0, mapping[&e1]), None);
+            assert_eq!(r_bar.relation(mapping[&e1], mapping[&e2]), Some(&Bar(1)));
+
+            r_bar.insert(e3, e1, Bar(2));
+            r_bar.copy_relations(e1, e4, CopyDirection::Both).unwrap();
+
+            // `e0` and `e3` keep their relation with `e1`, only `e1 -> e2` is copied
+            assert_eq!(r_bar.get_incoming(e1).count(), 2);
+            assert_eq!(r_bar.get_incoming(e4).count(), 0);
+            assert_eq!(r_bar.relation(e4, e2), Some(&Bar(1)));
+        },
+    );
+}
+
+#[test]
+fn test_get_mut() {
+    use shipyard::*;
+
+    use crate::{
+        relation_mode::{Directed, DirectedExclusiveOutgoing},
+        Relation, RelationViewMut,
+    };
+
+    #[derive(Debug, PartialEq)]
+    struct Strength(u32);
+
+    impl Relation for Strength {
+        type Mode = Directed;
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Target(u32);
+
+    impl Relation for Target {
+        type Mode = DirectedExclusiveOutgoing;
+    }
+
+    let mut world = World::new();
+
+    let e0 = world.add_entity(());
+    let e1 = world.add_entity(());
+    let e2 = world.add_entity(());
+
+    world.run(
+        |mut r_strength: RelationViewMut<Strength>, mut r_target: RelationViewMut<Target>| {
+            r_strength.insert(e0, e1, Strength(10));
+            r_strength.insert(e0, e2, Strength(20));
+            r_strength.insert(e1, e2, Strength(30));
+
+            for (_, strength) in r_strength.get_outgoing_mut(e0) {
+                strength.0 /= 2;
+            }
+
+            for (_, strength) in r_strength.get_incoming_mut(e2) {
+                strength.0 += 1;
+            }
+
+            assert_eq!(r_strength.relation(e0, e1), Some(&Strength(5)));
+            assert_eq!(r_strength.relation(e0, e2), Some(&Strength(11)));
+            assert_eq!(r_strength.relation(e1, e2), Some(&Strength(31)));
+
+            r_strength.for_each_outgoing_mut(e0, |_, strength| strength.0 *= 2);
+            r_strength.for_each_incoming_mut(e2, |a, strength| {
+                if a == e1 {
+                    strength.0 -= 1;
+                }
+            });
+
+            assert_eq!(r_strength.relation(e0, e1), Some(&Strength(10)));
+            assert_eq!(r_strength.relation(e0, e2), Some(&Strength(22)));
+            assert_eq!(r_strength.relation(e1, e2), Some(&Strength(30)));
+
+            r_target.insert(e0, e1, Target(0));
+
+            if let Some((target, data)) = r_target.get_outgoing_mut(e0) {
+                assert_eq!(target, e1);
+                data.0 = 1;
+            }
+
+            assert!(r_target.get_outgoing_mut(e1).is_none());
+            assert_eq!(r_target.get_incoming_mut(e1).count(), 1);
+            assert_eq!(r_target.relation(e0, e1), Some(&Target(1)));
+        },
+    );
+}
+
+#[test]
+fn test_retarget() {
+    use shipyard::*;
+
+    use crate::{
+        relation_mode::{DirectedExclusiveOutgoing, Undirected},
+        InsertError, Relation, RelationViewMut,
+    };
+
+    #[derive(Debug, PartialEq)]
+    struct ChildOf(u32);
+
+    impl Relation for ChildOf {
+        type Mode = DirectedExclusiveOutgoing;
+    }
+
+    struct Link;
+
+    impl Relation for Link {
+        type Mode = Undirected;
+    }
+
+    let mut world = World::new();
+
+    let e0 = world.add_entity(());
+    let e1 = world.add_entity(());
+    let e2 = world.add_entity(());
+    let e3 = world.add_entity(());
+
+    let mut r_child_of = world.borrow::<RelationViewMut<ChildOf>>().unwrap();
+
+    r_child_of.insert(e1, e0, ChildOf(1));
+    r_child_of.insert(e2, e1, ChildOf(2));
+    r_child_of.insert(e3, e0, ChildOf(3));
+
+    assert_eq!(r_child_of.retarget(e2, e1, e3), Ok(true));
+    assert_eq!(r_child_of.retarget(e2, e1, e3), Ok(false));
+    assert_eq!(
+        r_child_of.retarget(e3, e0, e2),
+        Err(InsertError::CycleDetected {
+            a: e3,
+            b: e2,
+            path: vec![e2, e3]
+        })
+    );
+
+    assert_eq!(r_child_of.get(e2).map(|e| e.0), Some(e3));
+    assert_eq!(r_child_of.relation(e2, e3), Some(&ChildOf(2)));
+    assert_eq!(r_child_of.get(e3).map(|e| e.0), Some(e0));
+
+    assert_eq!(r_child_of.deleted().count(), 0);
+    assert_eq!(
+        r_child_of.retargeted().collect::<Vec<_>>(),
+        vec![(e2, e1, e3)]
+    );
+    assert!(r_child_of.is_retargeted(e2, e3));
+    assert!(r_child_of.is_inserted(e2, e3));
+    assert!(!r_child_of.is_inserted(e2, e1));
+
+    drop(r_child_of);
+
+    let mut r_link = world.borrow::<RelationViewMut<Link>>().unwrap();
+
+    r_link.insert(e0, e1, Link);
+    r_link.insert(e1, e2, Link);
+    r_link.insert(e2, e3, Link);
+
+    // cycles are searched without going through the relation being moved
+    assert_eq!(
+        r_link.retarget(e1, e2, e0),
+        Err(InsertError::CycleDetected {
+            a: e1,
+            b: e0,
+            path: vec![e0, e1]
+        })
+    );
+    assert_eq!(r_link.retarget(e2, e1, e0), Ok(true));
+    assert!(r_link.relation(e0, e2).is_some());
+    assert!(r_link.relation(e1, e2).is_none());
+}
+
+#[test]
+fn test_hooks() {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    };
+
+    use shipyard::*;
+
+    use crate::{
+        relation_mode::DirectedExclusiveOutgoing, GetRelation, InsertError, Relation,
+        RelationViewMut,
+    };
+
+    #[derive(Debug, PartialEq)]
+    struct ChildOf(u32);
+
+    impl Relation for ChildOf {
+        type Mode = DirectedExclusiveOutgoing;
+    }
+
+    let mut world = World::new();
+
+    let e0 = world.add_entity(());
+    let e1 = world.add_entity(());
+    let e2 = world.add_entity(());
+    let e3 = world.add_entity(());
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+
+    let mut r_child_of = world.borrow::<RelationViewMut<ChildOf>>().unwrap();
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let on_insert_calls = calls.clone();
+    r_child_of.on_insert(move |_, b, relation| {
+        on_insert_calls.fetch_add(1, Ordering::Relaxed);
+        relation.0 != 0 && b != e3
+    });
+    let on_delete_events = events.clone();
+    r_child_of.on_delete(move |a, b, relation| {
+        on_delete_events
+            .lock()
+            .unwrap()
+            .push(("delete", a, b, relation.0))
+    });
+    let on_replace_events = events.clone();
+    r_child_of.on_replace(move |a, b, relation| {
+        on_replace_events
+            .lock()
+            .unwrap()
+            .push(("replace", a, b, relation.0))
+    });
+
+    assert_eq!(
+        r_child_of.insert_checked(e1, e0, ChildOf(0)),
+        Err(InsertError::Rejected)
+    );
+    assert_eq!(r_child_of.insert_checked(e1, e0, ChildOf(1)), Ok(()));
+    assert_eq!(r_child_of.insert_checked(e1, e0, ChildOf(2)), Ok(()));
+    assert_eq!(r_child_of.insert_checked(e1, e2, ChildOf(3)), Ok(()));
+
+    // once per new relation, replacing the data isn't an insertion
+    assert_eq!(calls.load(Ordering::Relaxed), 3);
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![("replace", e1, e0, 1), ("delete", e1, e0, 2)]
+    );
+
+    // checking doesn't ask the hook
+    assert_eq!(r_child_of.check_insert(e2, e3, &ChildOf(4)), Ok(()));
+    assert_eq!(calls.load(Ordering::Relaxed), 3);
+
+    assert_eq!(r_child_of.retarget(e1, e2, e3), Err(InsertError::Rejected));
+    assert_eq!(r_child_of.relation(e1, e2), Some(&ChildOf(3)));
+    assert_eq!(events.lock().unwrap().len(), 2);
+
+    assert_eq!(r_child_of.retarget(e1, e2, e0), Ok(true));
+    assert_eq!(events.lock().unwrap()[2], ("delete", e1, e2, 3));
+    assert_eq!(calls.load(Ordering::Relaxed), 5);
+
+    assert!(r_child_of.delete_relations_with(e1));
+    assert_eq!(events.lock().unwrap()[3], ("delete", e1, e0, 3));
+    assert!(r_child_of.is_deleted(e1, e0));
+}
+
+#[test]
+fn test_constraints() {
+    use shipyard::*;
+
+    use crate::{
+        relation_mode::Directed, GetRelation, InsertError, Relation, RelationViewMut, SelfLoops,
+    };
+
+    #[derive(Debug)]
+    struct Likes(u32);
+
+    impl Relation for Likes {
+        type Mode = Directed;
+
+        const ACYCLIC: bool = false;
+        const SELF_LOOPS: SelfLoops = SelfLoops::Reject;
+        const MAX_OUTGOING: Option<usize> = Some(2);
+        const MAX_INCOMING: Option<usize> = Some(1);
+        const SYMMETRIC_UNIQUE: bool = true;
+
+        fn validate<G>(_: &G, _: EntityId, _: EntityId, relation: &Self) -> Result<(), &'static str>
+        where
+            G: GetRelation<Self>,
+        {
+            if relation.0 > 10 {
+                Err("too much")
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    let mut world = World::new();
+
+    let e0 = world.add_entity(());
+    let e1 = world.add_entity(());
+    let e2 = world.add_entity(());
+    let e3 = world.add_entity(());
+
+    let mut r_likes = world.borrow::<RelationViewMut<Likes>>().unwrap();
+
+    assert_eq!(
+        r_likes.insert_checked(e0, e0, Likes(0)),
+        Err(InsertError::SelfLoop)
+    );
+    assert_eq!(r_likes.insert_checked(e0, e1, Likes(0)), Ok(()));
+    assert_eq!(
+        r_likes.insert_checked(e1, e0, Likes(0)),
+        Err(InsertError::SymmetricDuplicate)
+    );
+    assert_eq!(
+        r_likes.insert_checked(e2, e1, Likes(0)),
+        Err(InsertError::MaxIncomingExceeded)
+    );
+    assert_eq!(r_likes.insert_checked(e0, e2, Likes(0)), Ok(()));
+    assert_eq!(
+        r_likes.insert_checked(e0, e3, Likes(0)),
+        Err(InsertError::MaxOutgoingExceeded)
+    );
+    assert_eq!(
+        r_likes.insert_checked(e1, e3, Likes(11)),
+        Err(InsertError::Invalid("too much"))
+    );
+    assert_eq!(r_likes.insert_checked(e0, e1, Likes(1)), Ok(()));
+}
+
+#[test]
+fn test_self_loops() {
+    use core::marker::PhantomData;
+
+    use shipyard::*;
+
+    use crate::{relation_mode::*, GetRelation, InsertError, Relation, RelationViewMut, SelfLoops};
+
+    struct Allow<M>(PhantomData<M>);
+    struct Reject<M>(PhantomData<M>);
+    struct Ignore<M>(PhantomData<M>);
+
+    impl<M> Relation for Allow<M>
+    where
+        M: RelationMode + Send + Sync + 'static,
+    {
+        type Mode = M;
+
+        const SELF_LOOPS: SelfLoops = SelfLoops::Allow;
+    }
+
+    impl<M> Relation for Reject<M>
+    where
+        M: RelationMode + Send + Sync + 'static,
+    {
+        type Mode = M;
+
+        const SELF_LOOPS: SelfLoops = SelfLoops::Reject;
+    }
+
+    impl<M> Relation for Ignore<M>
+    where
+        M: RelationMode + Send + Sync + 'static,
+    {
+        type Mode = M;
+
+        const SELF_LOOPS: SelfLoops = SelfLoops::Ignore;
+    }
+
+    fn check<M>()
+    where
+        M: RelationMode + Send + Sync + 'static,
+    {
+        let mut world = World::new();
+
+        let e0 = world.add_entity(());
+        let e1 = world.add_entity(());
+
+        let mut r_allow = world.borrow::<RelationViewMut<Allow<M>>>().unwrap();
+
+        r_allow.insert(e0, e1, Allow(PhantomData));
+        assert_eq!(r_allow.insert_checked(e0, e0, Allow(PhantomData)), Ok(()));
+        assert!(r_allow.relation(e0, e0).is_some());
+        assert!(r_allow.is_inserted(e0, e0));
+
+        drop(r_allow);
+
+        let mut r_reject = world.borrow::<RelationViewMut<Reject<M>>>().unwrap();
+
+        r_reject.insert(e0, e1, Reject(PhantomData));
+        assert_eq!(
+            r_reject.insert_checked(e0, e0, Reject(PhantomData)),
+            Err(InsertError::SelfLoop)
+        );
+        assert!(r_reject.relation(e0, e0).is_none());
+        assert!(r_reject.relation(e0, e1).is_some());
+        assert_eq!(r_reject.deleted().count(), 0);
+
+        drop(r_reject);
+
+        let mut r_ignore = world.borrow::<RelationViewMut<Ignore<M>>>().unwrap();
+
+        r_ignore.insert(e0, e1, Ignore(PhantomData));
+        assert_eq!(r_ignore.insert_checked(e0, e0, Ignore(PhantomData)), Ok(()));
+        assert!(r_ignore.relation(e0, e0).is_none());
+        assert!(r_ignore.relation(e0, e1).is_some());
+        assert_eq!(r_ignore.inserted().count(), 1);
+        assert_eq!(r_ignore.deleted().count(), 0);
+    }
+
+    check::<Directed>();
+    check::<DirectedExclusive>();
+    check::<DirectedExclusiveIncoming>();
+    check::<DirectedExclusiveOutgoing>();
+    check::<Undirected>();
+    check::<UndirectedExclusive>();
+
+    // trees reject self-loops whatever the policy
+    let mut world = World::new();
+
+    let e0 = world.add_entity(());
+
+    let mut r_allow = world.borrow::<RelationViewMut<Allow<Tree>>>().unwrap();
+
+    assert_eq!(
+        r_allow.insert_checked(e0, e0, Allow(PhantomData)),
+        Err(InsertError::SelfLoop)
+    );
+    assert!(r_allow.relation(e0, e0).is_none());
+
+    drop(r_allow);
+
+    let mut r_ignore = world.borrow::<RelationViewMut<Ignore<Tree>>>().unwrap();
+
+    assert_eq!(
+        r_ignore.insert_checked(e0, e0, Ignore(PhantomData)),
+        Err(InsertError::SelfLoop)
+    );
+}
+
+#[test]
+fn test_undirected_tracking_order() {
+    use shipyard::*;
+
+    use crate::{relation_mode::Undirected, GetRelation, Relation, RelationViewMut};
+
+    struct Friends;
+
+    impl Relation for Friends {
+        type Mode = Undirected;
+    }
+
+    let mut world = World::new();
+
+    let e0 = world.add_entity(());
+    let e1 = world.add_entity(());
+    let e2 = world.add_entity(());
+
+    let mut r_friends = world.borrow::<RelationViewMut<Friends>>().unwrap();
+
+    r_friends.insert(e1, e0, Friends);
+    r_friends.insert(e2, e1, Friends);
+
+    assert_eq!(
+        r_friends.inserted().collect::<Vec<_>>(),
+        vec![(e0, e1), (e1, e2)]
+    );
+    assert!(r_friends.is_inserted(e1, e0));
+    assert!(r_friends.is_inserted(e0, e1));
+
+    r_friends.delete_relation(e0, e1);
+
+    assert_eq!(r_friends.inserted().collect::<Vec<_>>(), vec![(e1, e2)]);
+    assert_eq!(
+        r_friends.deleted().map(|(e, _)| e).collect::<Vec<_>>(),
+        vec![(e0, e1)]
+    );
+    assert!(r_friends.is_deleted(e1, e0));
+}
+
+#[test]
+fn test_deterministic() {
+    use shipyard::*;
+
+    use crate::{relation_mode::Directed, GetRelation, Relation, RelationViewMut};
+
+    struct Sorted;
+
+    impl Relation for Sorted {
+        type Mode = Directed;
+
+        const DETERMINISTIC: bool = true;
+    }
+
+    let mut world = World::new();
+
+    let e0 = world.add_entity(());
+    let e1 = world.add_entity(());
+    let e2 = world.add_entity(());
+    let e3 = world.add_entity(());
+
+    let mut r_sorted = world.borrow::<RelationViewMut<Sorted>>().unwrap();
+
+    r_sorted.insert(e0, e3, Sorted);
+    r_sorted.insert(e0, e1, Sorted);
+    r_sorted.insert(e0, e2, Sorted);
+    r_sorted.insert(e2, e3, Sorted);
+
+    assert_eq!(
+        r_sorted.get(e0).map(|(e, _)| e).collect::<Vec<_>>(),
+        vec![e1, e2, e3]
+    );
+    assert_eq!(
+        r_sorted.visit_breadth_first(e0).collect::<Vec<_>>(),
+        vec![e0, e1, e2, e3]
+    );
+
+    r_sorted.delete_relation(e0, e3);
+
+    assert_eq!(
+        r_sorted.inserted().collect::<Vec<_>>(),
+        vec![(e0, e1), (e0, e2), (e2, e3)]
+    );
+
+    r_sorted.delete_relation(e0, e1);
+
+    assert_eq!(
+        r_sorted.deleted().map(|(e, _)| e).collect::<Vec<_>>(),
+        vec![(e0, e3), (e0, e1)]
+    );
+}
+
+#[test]
+fn test_sparse_set() {
+    use shipyard::*;
+
+    use crate::{
+        relation_mode::{DirectedExclusiveIncoming, SparseSet, Undirected},
+        GetRelation, InsertError, Relation, RelationView, RelationViewMut,
+    };
+
+    struct Parent;
+
+    impl Relation for Parent {
+        type Mode = SparseSet<DirectedExclusiveIncoming>;
+    }
+
+    struct Friends;
+
+    impl Relation for Friends {
+        type Mode = SparseSet<Undirected>;
+
+        const ACYCLIC: bool = false;
+    }
+
+    let mut world = World::new();
+
+    let e0 = world.add_entity(());
+    let e1 = world.add_entity(());
+    let e2 = world.add_entity(());
+    let e3 = world.add_entity(());
+
+    let (mut r_parent, mut r_friends) = world
+        .borrow::<(RelationViewMut<Parent>, RelationViewMut<Friends>)>()
+        .unwrap();
+
+    r_parent.insert(e0, e1, Parent);
+    r_parent.insert(e0, e2, Parent);
+    r_parent.insert(e1, e3, Parent);
+
+    assert_eq!(r_parent.get_incoming(e3).map(|(e, _)| e), Some(e1));
+    assert_eq!(
+        r_parent.insert_checked(e3, e0, Parent),
+        Err(InsertError::CycleDetected {
+            a: e3,
+            b: e0,
+            path: vec![e0, e1, e3]
+        })
+    );
+
+    r_parent.insert(e2, e3, Parent);
+
+    assert_eq!(r_parent.get_incoming(e3).map(|(e, _)| e), Some(e2));
+    assert!(r_parent.relation(e1, e3).is_none());
+    assert_eq!(r_parent.visit_depth_first(e1).collect::<Vec<_>>(), vec![e1]);
+    assert_eq!(r_parent.visit_breadth_first(e0).count(), 4);
+
+    r_friends.insert(e1, e0, Friends);
+    r_friends.insert(e2, e1, Friends);
+    r_friends.insert(e0, e2, Friends);
+
+    assert_eq!(r_friends.get(e1).count(), 2);
+    assert!(r_friends.relation(e0, e1).is_some());
+
+    for (_, friends) in r_friends.get_mut(e0) {
+        *friends = Friends;
+    }
+
+    drop((r_parent, r_friends));
+
+    world.delete_entity(e2);
+
+    let (r_parent, r_friends) = world
+        .borrow::<(RelationView<Parent>, RelationView<Friends>)>()
+        .unwrap();
+
+    assert_eq!(
+        r_parent.get(e0).map(|(e, _)| e).collect::<Vec<_>>(),
+        vec![e1]
+    );
+    assert_eq!(r_parent.deleted().count(), 3);
+    assert_eq!(
+        r_friends.get(e0).map(|(e, _)| e).collect::<Vec<_>>(),
+        vec![e1]
+    );
+
+    drop((r_parent, r_friends));
+
+    world.run(
+        |mut entities: EntitiesViewMut, mut r_friends: RelationViewMut<Friends>| {
+            r_friends.insert(e1, e3, Friends);
+
+            // `e3` is deleted without its relations, then its index is reused
+            entities.delete_unchecked(e3);
+            let e4 = std::iter::repeat_with(|| entities.add_entity((), ()))
+                .find(|e| e.index() == e3.index())
+                .unwrap();
+
+            r_friends.insert(e4, e0, Friends);
+
+            assert!(r_friends.is_deleted(e1, e3));
+            assert_eq!(
+                r_friends.get(e1).map(|(e, _)| e).collect::<Vec<_>>(),
+                vec![e0]
+            );
+            assert_eq!(
+                r_friends.get(e4).map(|(e, _)| e).collect::<Vec<_>>(),
+                vec![e0]
+            );
+        },
+    );
+}
+
+#[test]
+fn test_retention() {
+    use shipyard::*;
+
+    use crate::{
+        relation_mode::Directed, GetRelation, Relation, RelationView, RelationViewMut, Retention,
+    };
+
+    struct Expiring;
+
+    impl Relation for Expiring {
+        type Mode = Directed;
+
+        const RETENTION: Retention = Retention::MaxAge(2);
+    }
+
+    let mut world = World::new();
+
+    let e0 = world.add_entity(());
+    let e1 = world.add_entity(());
+    let e2 = world.add_entity(());
+
+    {
+        let mut r_expiring = world.borrow::<RelationViewMut<Expiring>>().unwrap();
+
+        r_expiring.insert(e0, e1, Expiring);
+        r_expiring.insert(e0, e2, Expiring);
+        r_expiring.delete_relation(e0, e2);
+    }
+
+    {
+        let r_expiring = world.borrow::<RelationViewMut<Expiring>>().unwrap();
+
+        assert_eq!(r_expiring.storage.insertion_data.len(), 1);
+        assert_eq!(r_expiring.storage.deletion_data.len(), 1);
+    }
+
+    for _ in 0..4 {
+        drop(world.borrow::<RelationViewMut<Expiring>>().unwrap());
+    }
+
+    let mut r_expiring = world.borrow::<RelationViewMut<Expiring>>().unwrap();
+
+    assert!(r_expiring.storage.insertion_data.is_empty());
+    assert!(r_expiring.storage.deletion_data.is_empty());
+    assert!(r_expiring.relation(e0, e1).is_some());
+
+    r_expiring.insert(e1, e2, Expiring);
+    r_expiring.clear_inserted();
+
+    assert!(r_expiring.storage.insertion_data.is_empty());
+
+    r_expiring.delete_relation(e1, e2);
+    drop(r_expiring);
+
+    // clearing from the world applies the retention without borrowing a `RelationViewMut`
+    for _ in 0..4 {
+        drop(world.borrow::<RelationView<Expiring>>().unwrap());
+    }
+
+    world.clear_all_inserted();
+
+    let r_expiring = world.borrow::<RelationView<Expiring>>().unwrap();
+
+    assert!(r_expiring.storage.deletion_data.is_empty());
+    assert!(r_expiring.storage.oldest.is_none());
+
+    drop(r_expiring);
+
+    world
+        .borrow::<RelationViewMut<Expiring>>()
+        .unwrap()
+        .delete_relation(e0, e1);
+
+    for _ in 0..4 {
+        drop(world.borrow::<RelationView<Expiring>>().unwrap());
+    }
+
+    world.clear_all_inserted_and_modified();
+
+    let r_expiring = world.borrow::<RelationView<Expiring>>().unwrap();
+
+    assert!(r_expiring.storage.deletion_data.is_empty());
+}
+
+#[test]
+fn test_tracking() {
+    use shipyard::*;
+
+    use crate::{
+        relation_mode::Directed, GetRelation, Relation, RelationTracking, RelationViewMut,
+    };
+
+    struct Untracked;
+
+    impl Relation for Untracked {
+        type Mode = Directed;
+
+        const TRACKING: RelationTracking = RelationTracking::Untracked;
+    }
+
+    struct Deletion;
+
+    impl Relation for Deletion {
+        type Mode = Directed;
+
+        const TRACKING: RelationTracking = RelationTracking::Deletion;
+    }
+
+    let mut world = World::new();
+
+    let e0 = world.add_entity(());
+    let e1 = world.add_entity(());
+    let e2 = world.add_entity(());
+
+    let (mut r_untracked, mut r_deletion) = world
+        .borrow::<(RelationViewMut<Untracked>, RelationViewMut<Deletion>)>()
+        .unwrap();
+
+    r_untracked.insert(e0, e1, Untracked);
+    r_untracked.retarget(e0, e1, e2).unwrap();
+    r_untracked.delete_relation(e0, e2);
+
+    assert_eq!(r_untracked.inserted().count(), 0);
+    assert_eq!(r_untracked.retargeted().count(), 0);
+    assert_eq!(r_untracked.deleted().count(), 0);
+
+    r_deletion.insert(e0, e1, Deletion);
+
+    assert!(!r_deletion.is_inserted(e0, e1));
+
+    r_deletion.delete_relation(e0, e1);
+
+    assert!(r_deletion.is_deleted(e0, e1));
+}
+
+#[test]
+fn test_tracking_cursor() {
+    use shipyard::*;
+
+    use crate::{relation_mode::Directed, GetRelation, Relation, RelationViewMut, TrackingCursor};
+
+    struct Foo;
+
+    impl Relation for Foo {
+        type Mode = Directed;
+    }
+
+    let mut world = World::new();
+
+    let e0 = world.add_entity(());
+    let e1 = world.add_entity(());
+    let e2 = world.add_entity(());
+
+    let mut cursor = TrackingCursor::new();
+
+    world
+        .borrow::<RelationViewMut<Foo>>()
+        .unwrap()
+        .insert(e0, e1, Foo);
+
+    {
+        let r_foo = world.borrow::<RelationViewMut<Foo>>().unwrap();
+
+        assert_eq!(
+            r_foo.inserted_since(&mut cursor).collect::<Vec<_>>(),
+            vec![(e0, e1)]
+        );
+        assert_eq!(r_foo.deleted_since(&mut cursor).count(), 0);
+    }
+
+    {
+        let mut r_foo = world.borrow::<RelationViewMut<Foo>>().unwrap();
+
+        r_foo.insert(e1, e2, Foo);
+        r_foo.delete_relation(e0, e1);
+    }
+
+    let r_foo = world.borrow::<RelationViewMut<Foo>>().unwrap();
+
+    assert_eq!(
+        r_foo.inserted_since(&mut cursor).collect::<Vec<_>>(),
+        vec![(e1, e2)]
+    );
+    assert_eq!(
+        r_foo
+            .deleted_since(&mut cursor)
+            .map(|(e, _)| e)
+            .collect::<Vec<_>>(),
+        vec![(e0, e1)]
+    );
+    assert_eq!(r_foo.inserted_since(&mut cursor).count(), 0);
+
+    drop(r_foo);
+
+    {
+        let mut r_foo = world.borrow::<RelationViewMut<Foo>>().unwrap();
+
+        r_foo.insert(e2, e0, Foo);
+        r_foo.retarget(e1, e2, e0).unwrap();
+    }
+
+    let r_foo = world.borrow::<RelationViewMut<Foo>>().unwrap();
+
+    // the cursor doesn't move until all events are seen
+    assert_eq!(r_foo.inserted_since(&mut cursor).next(), Some((e2, e0)));
+    assert_eq!(r_foo.inserted_since(&mut cursor).count(), 1);
+    assert_eq!(r_foo.inserted_since(&mut cursor).count(), 0);
+
+    assert_eq!(
+        r_foo.retargeted_since(&mut cursor).collect::<Vec<_>>(),
+        vec![(e1, e2, e0)]
+    );
+    assert_eq!(r_foo.retargeted_since(&mut cursor).count(), 0);
+
+    drop(r_foo);
+
+    // events recorded after an earlier call in the same system show up on the next call
+    world.run(|mut r_foo: RelationViewMut<Foo>| {
+        r_foo.delete_relation(e2, e0);
+
+        assert_eq!(r_foo.deleted_since(&mut cursor).count(), 1);
+
+        r_foo.delete_relation(e1, e0);
+
+        assert_eq!(
+            r_foo
+                .deleted_since(&mut cursor)
+                .map(|(e, _)| e)
+                .collect::<Vec<_>>(),
+            vec![(e1, e0)]
+        );
+        assert_eq!(r_foo.deleted_since(&mut cursor).count(), 0);
+    });
+}
+
+#[test]
+fn test_clear_and_retain() {
+    use shipyard::*;
+
+    use crate::{relation_mode::Undirected, GetRelation, Relation, RelationView, RelationViewMut};
+
+    struct Distance(u32);
+
+    impl Relation for Distance {
+        type Mode = Undirected;
+    }
+
+    let mut world = World::new();
+
+    let e0 = world.add_entity(());
+    let e1 = world.add_entity(());
+    let e2 = world.add_entity(());
+    let e3 = world.add_entity(());
+
+    {
+        let mut r_distance = world.borrow::<RelationViewMut<Distance>>().unwrap();
+
+        r_distance.insert(e1, e0, Distance(1));
+        r_distance.insert(e1, e2, Distance(5));
+        r_distance.insert(e3, e2, Distance(2));
+
+        let mut visited = Vec::new();
+        r_distance.retain(|a, b, distance| {
+            visited.push((a, b));
+            distance.0 += 1;
+            distance.0 < 4
+        });
+        visited.sort_unstable();
+
+        assert_eq!(visited, vec![(e0, e1), (e1, e2), (e2, e3)]);
+        assert!(r_distance.relation(e1, e2).is_none());
+        assert_eq!(r_distance.relation(e0, e1).map(|d| d.0), Some(2));
+        assert!(r_distance.is_deleted(e2, e1));
+    }
+
+    world.all_storages_mut().unwrap().clear();
+
+    let r_distance = world.borrow::<RelationView<Distance>>().unwrap();
+
+    assert!(r_distance.relation(e0, e1).is_none());
+    assert!(r_distance.relation(e2, e3).is_none());
+    assert_eq!(r_distance.deleted().count(), 3);
+    assert!(r_distance.storage.is_empty());
+}
+
+#[test]
+fn test_clone_and_move() {
+    use std::collections::HashMap;
+
+    use shipyard::*;
+
+    use crate::{
+        relation_mode::{Directed, DirectedExclusiveOutgoing},
+        GetRelation, InsertError, Relation, RelationView, RelationViewMut,
+    };
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Link(u32);
+
+    impl Relation for Link {
+        type Mode = Directed;
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Next;
+
+    impl Relation for Next {
+        type Mode = DirectedExclusiveOutgoing;
+
+        const ACYCLIC: bool = true;
+    }
+
+    let mut prefab = World::new();
+
+    let p0 = prefab.add_entity(());
+    let p1 = prefab.add_entity(());
+    let p2 = prefab.add_entity(());
+
+    let mut world = World::new();
+
+    let w0 = world.add_entity(());
+    let w1 = world.add_entity(());
+    let w2 = world.add_entity(());
+
+    {
+        let mut r_link = prefab.borrow::<RelationViewMut<Link>>().unwrap();
+
+        r_link.insert(p0, p1, Link(1));
+        r_link.insert(p1, p2, Link(2));
+
+        let storage = r_link.storage.clone();
+
+        assert_eq!(storage.graph.edge_weight(p0, p1), Some(&Link(1)));
+        assert_eq!(storage.insertion_data.len(), 2);
+    }
+
+    {
+        let r_prefab = prefab.borrow::<RelationView<Link>>().unwrap();
+        let mut r_world = world.borrow::<RelationViewMut<Link>>().unwrap();
+
+        r_world.insert(w2, w0, Link(0));
+        r_world.clone_relations_from(&r_prefab).unwrap();
+
+        assert!(r_world.relation(w2, w0).is_none());
+        assert_eq!(r_world.relation(p1, p2), Some(&Link(2)));
+    }
+
+    {
+        let r_prefab = prefab.borrow::<RelationView<Link>>().unwrap();
+        let mut r_world = world.borrow::<RelationViewMut<Link>>().unwrap();
+
+        r_world.retain(|a, _, _| a != p0);
+        r_world.on_insert(|_, _, relation| relation.0 != 2);
+
+        assert_eq!(
+            r_world.clone_relations_from(&r_prefab),
+            Err(InsertError::Rejected)
+        );
+        assert!(r_world.relation(p0, p1).is_none());
+        assert_eq!(r_world.relation(p1, p2), Some(&Link(2)));
+    }
+
+    let mut r_prefab = prefab.borrow::<RelationViewMut<Link>>().unwrap();
+    let mut r_world = world.borrow::<RelationViewMut<Link>>().unwrap();
+
+    r_world.clear();
+
+    let mapping = HashMap::from([(p0, w2), (p1, w1), (p2, w0)]);
+    assert_eq!(
+        r_prefab.move_relations_to(&mut r_world, &mapping),
+        Err(InsertError::Rejected)
+    );
+    assert!(r_world.relation(w2, w1).is_none());
+    assert_eq!(r_prefab.relation(p0, p1), Some(&Link(1)));
+    assert_eq!(r_prefab.relation(p1, p2), Some(&Link(2)));
+    assert!(!r_prefab.is_deleted(p1, p2));
+
+    let mapping = HashMap::from([(p0, w2), (p1, w1)]);
+    r_prefab.move_relations_to(&mut r_world, &mapping).unwrap();
+
+    assert_eq!(r_world.relation(w2, w1), Some(&Link(1)));
+    assert!(r_world.get_incoming(w2).next().is_none());
+    assert!(r_prefab.relation(p0, p1).is_none());
+    assert!(r_prefab.relation(p1, p2).is_none());
+    assert!(r_prefab.is_deleted(p1, p2));
+    assert!(!r_prefab.is_deleted(p0, p1));
+
+    drop((r_prefab, r_world));
+
+    let p3 = prefab.add_entity(());
+    let w3 = world.add_entity(());
+    let w4 = world.add_entity(());
+
+    let mut r_prefab = prefab.borrow::<RelationViewMut<Next>>().unwrap();
+    let mut r_world = world.borrow::<RelationViewMut<Next>>().unwrap();
+
+    r_prefab.insert(p0, p1, Next);
+    r_prefab.insert(p1, p2, Next);
+    r_prefab.insert(p2, p3, Next);
+    r_world.insert(w0, w4, Next);
+    r_world.insert(w1, w4, Next);
+    r_world.insert(w3, w0, Next);
+
+    // the first two moves replace relations of `world`, the third one closes a cycle
+    let mapping = HashMap::from([(p0, w0), (p1, w1), (p2, w2), (p3, w3)]);
+    assert!(matches!(
+        r_prefab.move_relations_to(&mut r_world, &mapping),
+        Err(InsertError::CycleDetected { .. })
+    ));
+    assert_eq!(r_world.get(w0).map(|e| e.0), Some(w4));
+    assert_eq!(r_world.get(w1).map(|e| e.0), Some(w4));
+    assert!(r_world.get(w2).is_none());
+    assert_eq!(r_world.get(w3).map(|e| e.0), Some(w0));
+    assert_eq!(r_world.deleted().count(), 0);
+    assert_eq!(r_prefab.get(p1).map(|e| e.0), Some(p2));
+    assert_eq!(r_prefab.get(p2).map(|e| e.0), Some(p3));
+
+    r_world.delete_relation(w3, w0);
+    r_prefab.move_relations_to(&mut r_world, &mapping).unwrap();
+
+    assert_eq!(r_world.get(w0).map(|e| e.0), Some(w1));
+    assert_eq!(r_world.get(w2).map(|e| e.0), Some(w3));
+    assert!(r_world.is_deleted(w1, w4));
+    assert!(r_prefab.storage.is_empty());
+}
+
+#[test]
+fn test_markers() {
+    use shipyard::*;
+
+    use crate::{
+        relation_mode::DirectedExclusiveOutgoing, sync_relation_components, HasIncoming,
+        HasOutgoing, Relation, RelationExt, RelationViewMut,
+    };
+
+    struct ChildOf;
+
+    impl Relation for ChildOf {
+        type Mode = DirectedExclusiveOutgoing;
+
+        const MARKERS: bool = true;
+    }
+
+    let mut world = World::new();
+
+    let e0 = world.add_entity(());
+    let e1 = world.add_entity(());
+    let e2 = world.add_entity(());
+
+    world.add_relation(e1, e0, ChildOf).unwrap();
+    world.add_relation(e2, e0, ChildOf).unwrap();
+
+    world.run(
+        |has_outgoing: View<HasOutgoing<ChildOf>>, has_incoming: View<HasIncoming<ChildOf>>| {
+            assert!(has_outgoing.contains(e1));
+            assert!(has_outgoing.contains(e2));
+            assert!(!has_outgoing.contains(e0));
+            assert!(has_incoming.contains(e0));
+            assert!(!has_incoming.contains(e1));
+        },
+    );
+
+    world
+        .borrow::<RelationViewMut<ChildOf>>()
+        .unwrap()
+        .delete_relation(e1, e0);
+
+    assert!(!world
+        .borrow::<View<HasOutgoing<ChildOf>>>()
+        .unwrap()
+        .contains(e1));
+
+    world.delete_entity(e2);
+
+    // deleting an entity outside of a view leaves its former partners outdated
+    assert!(world
+        .borrow::<View<HasIncoming<ChildOf>>>()
+        .unwrap()
+        .contains(e0));
+
+    world.run(sync_relation_components::<ChildOf>);
+
+    assert!(!world
+        .borrow::<View<HasIncoming<ChildOf>>>()
+        .unwrap()
+        .contains(e0));
+}
+
+#[test]
+fn test_mirror() {
+    use shipyard::*;
+
+    use crate::{
+        relation_mode::{DirectedExclusiveIncoming, DirectedExclusiveOutgoing},
+        sync_relation_components, Relation, RelationViewMut, Source, Target,
+    };
+
+    struct ChildOf;
+
+    impl Relation for ChildOf {
+        type Mode = DirectedExclusiveOutgoing;
+
+        const MIRROR: bool = true;
+    }
+
+    struct Owns;
+
+    impl Relation for Owns {
+        type Mode = DirectedExclusiveIncoming;
+
+        const MIRROR: bool = true;
+    }
+
+    let mut world = World::new();
+
+    let e0 = world.add_entity(());
+    let e1 = world.add_entity(());
+    let e2 = world.add_entity(());
+
+    {
+        let (mut r_child_of, mut r_owns) = world
+            .borrow::<(RelationViewMut<ChildOf>, RelationViewMut<Owns>)>()
+            .unwrap();
+
+        r_child_of.insert(e1, e0, ChildOf);
+        r_child_of.insert(e2, e0, ChildOf);
+        r_child_of.retarget(e2, e0, e1).unwrap();
+
+        r_owns.insert(e0, e2, Owns);
+    }
+
+    world.run(
+        |target: View<Target<ChildOf>>, source: View<Source<Owns>>| {
+            assert_eq!(target.get(e1).unwrap().0, e0);
+            assert_eq!(target.get(e2).unwrap().0, e1);
+            assert!(!target.contains(e0));
+            assert_eq!(source.get(e2).unwrap().0, e0);
+        },
+    );
+
+    world.delete_entity(e0);
+
+    // stale until the components are synced
+    world.run(
+        |target: View<Target<ChildOf>>, source: View<Source<Owns>>| {
+            assert_eq!(target.get(e1).unwrap().0, e0);
+            assert_eq!(source.get(e2).unwrap().0, e0);
+        },
+    );
+
+    world.run(sync_relation_components::<ChildOf>);
+    world.run(sync_relation_components::<Owns>);
+
+    world.run(
+        |target: View<Target<ChildOf>>, source: View<Source<Owns>>| {
+            assert!(!target.contains(e1));
+            assert_eq!(target.get(e2).unwrap().0, e1);
+            assert!(!source.contains(e2));
+        },
+    );
+}
+
+#[test]
+fn test_propagate_and_fold() {
+    use shipyard::*;
+
+    use crate::{
+        relation_mode::{Directed, DirectedExclusiveIncoming},
+        GetRelation, Relation, RelationViewMut,
+    };
+
+    struct Contains(u32);
+
+    impl Relation for Contains {
+        type Mode = DirectedExclusiveIncoming;
+
+        const ACYCLIC: bool = true;
+    }
+
+    struct Before;
+
+    impl Relation for Before {
+        type Mode = Directed;
+    }
+
+    let mut world = World::new();
+
+    let [e0, e1, e2, e3, e4, e5] = [(); 6].map(|_| world.add_entity(()));
+
+    {
+        let mut r = world.borrow::<RelationViewMut<Contains>>().unwrap();
+
+        r.insert(e0, e1, Contains(2));
+        r.insert(e0, e2, Contains(1));
+        r.insert(e1, e3, Contains(3));
+
+        let weights = r.fold_up(|children, _| {
+            1 + children
+                .map(|(weight, contains)| weight * contains.0)
+                .sum::<u32>()
+        });
+
+        assert_eq!(weights[&e3], 1);
+        assert_eq!(weights[&e1], 4);
+        assert_eq!(weights[&e0], 10);
+        assert!(!weights.contains_key(&e4));
+
+        let mut depths = r.propagate_down([e0], 0, |depth, _, _| depth + 1);
+
+        assert_eq!(depths.len(), 4);
+        assert_eq!(depths[&e0], 0);
+        assert_eq!(depths[&e2], 1);
+        assert_eq!(depths[&e3], 2);
+
+        r.clear_inserted();
+        r.insert(e3, e4, Contains(1));
+
+        r.propagate_down_inserted(&mut depths, |depth, _, _| depth + 1);
+
+        assert_eq!(depths.len(), 5);
+        assert_eq!(depths[&e4], 3);
+
+        // `e3` moves up along with `e4`, and gets a new child in the same batch
+        r.clear_inserted();
+        r.insert(e0, e3, Contains(1));
+        r.insert(e3, e5, Contains(1));
+
+        r.propagate_down_inserted(&mut depths, |depth, _, _| depth + 1);
+
+        assert_eq!(depths[&e3], 1);
+        assert_eq!(depths[&e4], 2);
+        assert_eq!(depths[&e5], 2);
+
+        r.clear_inserted();
+        assert_eq!(r.retarget(e0, e3, e4), Ok(true));
+
+        r.propagate_down_inserted(&mut depths, |depth, _, _| depth + 1);
+
+        assert_eq!(depths[&e4], 1);
+        assert_eq!(depths[&e5], 2);
+    }
+
+    let mut r = world.borrow::<RelationViewMut<Before>>().unwrap();
+
+    r.insert(e0, e1, Before);
+    r.insert(e1, e2, Before);
+    r.insert(e2, e3, Before);
+    r.insert(e0, e3, Before);
+
+    let mut depths = r.propagate_down([e0], 0, |depth, _, _| depth + 1);
+
+    assert_eq!(depths[&e3], 3);
+
+    // `e3` only has `e0` left to compute its depth from
+    r.clear_inserted();
+    r.delete_relation(e2, e3);
+
+    r.propagate_down_inserted(&mut depths, |depth, _, _| depth + 1);
+
+    assert_eq!(depths[&e3], 1);
+}
+
+#[test]
+fn test_ancestry() {
+    use shipyard::*;
+
+    use crate::{
+        relation_mode::{Directed, DirectedExclusiveIncoming},
+        GetRelation, Relation, RelationViewMut,
+    };
+
+    struct Parent;
+
+    impl Relation for Parent {
+        type Mode = DirectedExclusiveIncoming;
+    }
+
+    struct DependsOn;
+
+    impl Relation for DependsOn {
+        type Mode = Directed;
+
+        const ACYCLIC: bool = false;
+    }
+
+    let mut world = World::new();
+
+    let [e0, e1, e2, e3, e4, e5] = [(); 6].map(|_| world.add_entity(()));
+
+    let (mut r_parent, mut r_depends_on) = world
+        .borrow::<(RelationViewMut<Parent>, RelationViewMut<DependsOn>)>()
+        .unwrap();
+
+    r_parent.insert(e0, e1, Parent);
+    r_parent.insert(e0, e2, Parent);
+    r_parent.insert(e1, e3, Parent);
+    r_parent.insert(e3, e4, Parent);
+
+    assert_eq!(r_parent.ancestors(e4).collect::<Vec<_>>(), vec![e3, e1, e0]);
+    assert_eq!(r_parent.descendants(e1).collect::<Vec<_>>(), vec![e3, e4]);
+    assert!(r_parent.is_reachable(e0, e4));
+    assert!(!r_parent.is_reachable(e4, e0));
+    assert!(!r_parent.is_reachable(e2, e4));
+    assert!(r_parent.path_exists(e2, e4));
+    assert!(!r_parent.path_exists(e2, e5));
+    assert_eq!(r_parent.lowest_common_ancestor(e4, e2), Some(e0));
+    assert_eq!(r_parent.lowest_common_ancestor(e4, e1), Some(e1));
+    assert_eq!(r_parent.lowest_common_ancestor(e4, e5), None);
+
+    r_depends_on.insert(e0, e1, DependsOn);
+    r_depends_on.insert(e1, e2, DependsOn);
+    r_depends_on.insert(e2, e0, DependsOn);
+    r_depends_on.insert(e2, e3, DependsOn);
+
+    assert!(r_depends_on.is_reachable(e1, e0));
+    assert!(r_depends_on.is_reachable(e0, e3));
+    assert!(!r_depends_on.is_reachable(e3, e0));
+    assert_eq!(r_depends_on.ancestors(e3).count(), 3);
+    assert_eq!(r_depends_on.descendants(e0).count(), 3);
+}
+
+#[test]
+fn test_tree() {
+    use shipyard::*;
+
+    use crate::{
+        relation_mode::{SparseSet, Tree},
+        GetRelation, InsertError, Relation, RelationViewMut,
+    };
+
+    #[derive(Clone)]
+    struct ChildOf;
+
+    impl Relation for ChildOf {
+        type Mode = Tree;
+
+        const ACYCLIC: bool = false;
+    }
+
+    struct SparseChildOf;
+
+    impl Relation for SparseChildOf {
+        type Mode = SparseSet<Tree>;
+    }
+
+    let mut world = World::new();
+
+    let [e0, e1, e2, e3, e4] = [(); 5].map(|_| world.add_entity(()));
+
+    let (mut entities, mut r_child_of, mut r_sparse) = world
+        .borrow::<(
+            EntitiesViewMut,
+            RelationViewMut<ChildOf>,
+            RelationViewMut<SparseChildOf>,
+        )>()
+        .unwrap();
+
+    r_child_of.insert(e1, e0, ChildOf);
+    r_child_of.insert(e2, e0, ChildOf);
+    r_child_of.insert(e3, e1, ChildOf);
+
+    // relations go from child to parent, hierarchy queries go from parent to child
+    assert_eq!(r_child_of.ancestors(e3).collect::<Vec<_>>(), vec![e1, e0]);
+    let mut descendants = r_child_of.descendants(e0).collect::<Vec<_>>();
+    descendants.sort_unstable();
+    assert_eq!(descendants, vec![e1, e2, e3]);
+    assert_eq!(r_child_of.lowest_common_ancestor(e3, e2), Some(e0));
+    assert_eq!(r_child_of.lowest_common_ancestor(e3, e1), Some(e1));
+
+    let depths = r_child_of.propagate_down([e0], 0, |depth, _, _| depth + 1);
+    assert_eq!(depths.len(), 4);
+    assert_eq!(depths[&e2], 1);
+    assert_eq!(depths[&e3], 2);
+
+    let sizes =
+        r_child_of.fold_up(|children, _| 1 + children.map(|(size, _)| *size).sum::<usize>());
+    assert_eq!(sizes[&e0], 4);
+    assert_eq!(sizes[&e1], 2);
+    assert_eq!(sizes[&e3], 1);
+
+    assert_eq!(r_child_of.get(e3).map(|(e, _)| e), Some(e1));
+    assert_eq!(r_child_of.roots().collect::<Vec<_>>(), vec![e0]);
+    assert!(r_child_of.is_leaf(e2));
+    assert!(!r_child_of.is_leaf(e1));
+    assert_eq!(r_child_of.depth(e3), 2);
+    assert_eq!(r_child_of.depth(e0), 0);
+    assert_eq!(r_child_of.subtree_size(e0), 4);
+    assert_eq!(r_child_of.subtree_size(e1), 2);
+
+    assert_eq!(
+        r_child_of.insert_checked(e0, e3, ChildOf),
+        Err(InsertError::CycleDetected {
+            a: e0,
+            b: e3,
+            path: vec![e3, e1, e0]
+        })
+    );
+    assert_eq!(
+        r_child_of.insert_checked(e4, e4, ChildOf),
+        Err(InsertError::SelfLoop)
+    );
+    assert_eq!(
+        r_child_of.retarget(e1, e0, e3),
+        Err(InsertError::CycleDetected {
+            a: e1,
+            b: e3,
+            path: vec![e3, e1]
+        })
+    );
+
+    r_child_of.insert(e1, e2, ChildOf);
+
+    assert_eq!(r_child_of.depth(e3), 3);
+
+    // the clone of `e1` is attached to the same parent, the one of `e3` to it
+    let mapping = r_child_of.clone_subtree(&mut entities, e1).unwrap();
+
+    assert_eq!(mapping.len(), 2);
+    assert_eq!(r_child_of.get(mapping[&e1]).map(|(e, _)| e), Some(e2));
+    assert_eq!(
+        r_child_of.get(mapping[&e3]).map(|(e, _)| e),
+        Some(mapping[&e1])
+    );
+    assert_eq!(r_child_of.get(e3).map(|(e, _)| e), Some(e1));
+
+    r_sparse.insert(e1, e0, SparseChildOf);
+    r_sparse.insert(e2, e1, SparseChildOf);
+
+    assert!(r_sparse.insert_checked(e0, e2, SparseChildOf).is_err());
+    assert_eq!(r_sparse.depth(e2), 2);
+    assert_eq!(r_sparse.subtree_size(e0), 3);
+}
+
+#[test]
+fn test_roots_and_leaves() {
+    use shipyard::*;
+
+    use crate::{
+        relation_mode::{Directed, SparseSet, Tree, Undirected},
+        GetRelation, Relation, RelationViewMut,
+    };
+
+    struct DependsOn;
+
+    impl Relation for DependsOn {
+        type Mode = Directed;
+
+        const DETERMINISTIC: bool = true;
+    }
+
+    struct ChildOf;
+
+    impl Relation for ChildOf {
+        type Mode = SparseSet<Tree>;
+
+        const DETERMINISTIC: bool = true;
+    }
+
+    struct Friends;
+
+    impl Relation for Friends {
+        type Mode = Undirected;
+
+        const ACYCLIC: bool = false;
+        const DETERMINISTIC: bool = true;
+    }
+
+    let mut world = World::new();
+
+    let [e0, e1, e2, e3] = [(); 4].map(|_| world.add_entity(()));
+
+    {
+        let (mut r_depends_on, mut r_child_of, mut r_friends) = world
+            .borrow::<(
+                RelationViewMut<DependsOn>,
+                RelationViewMut<ChildOf>,
+                RelationViewMut<Friends>,
+            )>()
+            .unwrap();
+
+        r_depends_on.insert(e0, e1, DependsOn);
+        r_depends_on.insert(e1, e2, DependsOn);
+        r_depends_on.insert(e3, e2, DependsOn);
+
+        assert_eq!(r_depends_on.roots().collect::<Vec<_>>(), vec![e0, e3]);
+        assert_eq!(r_depends_on.leaves().collect::<Vec<_>>(), vec![e2]);
+        assert_eq!(r_depends_on.isolated().count(), 0);
+
+        r_depends_on.delete_relation(e3, e2);
+
+        assert_eq!(r_depends_on.roots().collect::<Vec<_>>(), vec![e0]);
+        assert_eq!(r_depends_on.isolated().collect::<Vec<_>>(), vec![e3]);
+
+        r_child_of.insert(e1, e0, ChildOf);
+        r_child_of.insert(e2, e1, ChildOf);
+        r_child_of.insert(e3, e0, ChildOf);
+
+        assert_eq!(r_child_of.roots().collect::<Vec<_>>(), vec![e0]);
+        assert_eq!(r_child_of.leaves().collect::<Vec<_>>(), vec![e2, e3]);
+
+        r_child_of.retarget(e2, e1, e3).unwrap();
+
+        assert_eq!(r_child_of.leaves().collect::<Vec<_>>(), vec![e1, e2]);
+
+        r_friends.insert(e0, e1, Friends);
+        r_friends.insert(e1, e2, Friends);
+
+        assert_eq!(r_friends.roots().count(), 0);
+        assert_eq!(r_friends.leaves().collect::<Vec<_>>(), vec![e0, e2]);
+    }
+
+    world.delete_entity(e1);
+
+    let r_friends = world.borrow::<RelationViewMut<Friends>>().unwrap();
+
+    assert_eq!(r_friends.leaves().count(), 0);
+    assert_eq!(r_friends.isolated().collect::<Vec<_>>(), vec![e0, e2]);
+}
+
+#[test]
+fn test_cycles() {
+    use shipyard::*;
+
+    use crate::{relation_mode::Directed, GetRelation, Relation, RelationViewMut};
+
+    struct Aggro;
+
+    impl Relation for Aggro {
+        type Mode = Directed;
+
+        const ACYCLIC: bool = false;
+        const DETERMINISTIC: bool = true;
+    }
+
+    let mut world = World::new();
+
+    let [e0, e1, e2, e3, e4] = [(); 5].map(|_| world.add_entity(()));
+
+    let mut r = world.borrow::<RelationViewMut<Aggro>>().unwrap();
+
+    r.insert(e0, e1, Aggro);
+    r.insert(e1, e2, Aggro);
+    r.insert(e2, e3, Aggro);
+
+    assert_eq!(r.find_cycle(), None);
+    assert_eq!(r.strongly_connected_components().len(), 4);
+    assert!(r.cycles_through(e0).is_empty());
+
+    r.insert(e2, e0, Aggro);
+    r.insert(e1, e0, Aggro);
+    r.insert(e3, e4, Aggro);
+
+    let mut components = r.strongly_connected_components();
+    components.sort();
+
+    assert_eq!(components, vec![vec![e0, e1, e2], vec![e3], vec![e4]]);
+    assert_eq!(r.find_cycle(), Some(vec![e0, e1]));
+
+    let mut cycles = r.cycles_through(e0);
+    cycles.sort();
+
+    assert_eq!(cycles, vec![vec![e0, e1], vec![e0, e1, e2]]);
+    assert!(r.cycles_through(e3).is_empty());
+}
+
+#[test]
+fn test_petgraph_visit() {
+    use petgraph::{
+        algo::{dijkstra, toposort},
+        visit::{Dfs, IntoEdgeReferences, NodeCount},
+    };
+    use shipyard::*;
+
+    use crate::{relation_mode::Directed, Relation, RelationView, RelationViewMut};
+
+    struct Road(u32);
+
+    impl Relation for Road {
+        type Mode = Directed;
+
+        const DETERMINISTIC: bool = true;
+    }
+
+    let mut world = World::new();
+
+    let [e0, e1, e2, e3] = [(); 4].map(|_| world.add_entity(()));
+
+    {
+        let mut r = world.borrow::<RelationViewMut<Road>>().unwrap();
+
+        r.insert(e0, e1, Road(1));
+        r.insert(e1, e2, Road(1));
+        r.insert(e0, e2, Road(5));
+        r.insert(e2, e3, Road(2));
+
+        assert_eq!(toposort(&r, None).unwrap(), vec![e0, e1, e2, e3]);
+    }
+
+    let r = world.borrow::<RelationView<Road>>().unwrap();
+
+    assert_eq!((&r).node_count(), 4);
+    assert_eq!((&r).edge_references().count(), 4);
+
+    let costs = dijkstra(&r, e0, None, |(_, _, road)| road.0);
+
+    assert_eq!(costs[&e2], 2);
+    assert_eq!(costs[&e3], 4);
+
+    let mut dfs = Dfs::new(&r, e1);
+    let mut visited = Vec::new();
+    while let Some(entity) = dfs.next(&r) {
+        visited.push(entity);
+    }
+
+    assert_eq!(visited, vec![e1, e2, e3]);
+}
+
+#[test]
+fn test_filtered() {
+    use shipyard::*;
+
+    use crate::{relation_mode::Undirected, GetRelation, Relation, RelationViewMut};
+
+    #[derive(Component)]
+    struct Powered;
+
+    struct Wire;
+
+    impl Relation for Wire {
+        type Mode = Undirected;
+
+        const ACYCLIC: bool = false;
+        const DETERMINISTIC: bool = true;
+    }
+
+    let mut world = World::new();
+
+    let e0 = world.add_entity(Powered);
+    let e1 = world.add_entity(Powered);
+    let e2 = world.add_entity(());
+    let e3 = world.add_entity(Powered);
+    let e4 = world.add_entity(Powered);
+
+    let (mut r, powered) = world
+        .borrow::<(RelationViewMut<Wire>, View<Powered>)>()
+        .unwrap();
+
+    r.insert(e0, e1, Wire);
+    r.insert(e1, e2, Wire);
+    r.insert(e2, e3, Wire);
+    r.insert(e1, e4, Wire);
+    r.insert(e4, e3, Wire);
+
+    let filtered = r.with_component(&powered);
+
+    assert_eq!(
+        filtered.get(e1).map(|(e, _)| e).collect::<Vec<_>>(),
+        vec![e0, e4]
+    );
+    assert!(filtered.relation(e1, e2).is_none());
+    assert_eq!(
+        filtered.visit_depth_first(e0).collect::<Vec<_>>(),
+        vec![e0, e1, e4, e3]
+    );
+    assert_eq!(filtered.visit_breadth_first(e2).count(), 0);
+    assert_eq!(filtered.path(e0, e3), Some(vec![e0, e1, e4, e3]));
+    assert_eq!(r.storage.path(e0, e3), Some(vec![e0, e1, e2, e3]));
+
+    let filtered = r.filtered(|e| e != e2 && e != e4);
+
+    assert!(!filtered.is_reachable(e0, e3));
+    assert!(filtered.is_reachable(e1, e0));
+}
@@ -1,17 +1,157 @@
+use std::collections::{HashMap, HashSet};
+
 use petgraph::EdgeType;
-use shipyard::{EntityId, TrackingTimestamp};
+use shipyard::{Component, EntityId, TrackingTimestamp, View};
 
 use crate::{
-    iter::{BreadthFirstIter, DepthFirstIter},
-    relation_mode::RelationMode,
+    adjacency::Adjacency,
+    filtered::FilteredRelation,
+    iter::{BreadthFirstIter, DepthFirstIter, LineageIter},
+    relation_mode::{RelationMode, TreeMode},
     storage::RelationStorage,
 };
 
+/// Policy for relations of an entity with itself, see `Relation::SELF_LOOPS`.
+///
+/// The policy is applied before anything else, the same way for every `RelationMode`
+/// except acyclic ones like `Tree`, which reject self-loops whatever the policy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelfLoops {
+    /// Self-loops are inserted like any other relation.
+    /// They don't count as cycles, even for acyclic relations.
+    Allow,
+    /// Self-loops are rejected with `InsertError::SelfLoop`.
+    Reject,
+    /// Self-loops are silently dropped.
+    Ignore,
+}
+
+/// Which events are tracked for a relation type, see `Relation::TRACKING`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelationTracking {
+    /// Nothing is tracked.
+    Untracked,
+    /// Insertions and retargetings are tracked.
+    Insertion,
+    /// Deletions are tracked.
+    Deletion,
+    /// Insertions, retargetings and deletions are tracked.
+    All,
+}
+
+impl RelationTracking {
+    pub const fn tracks_insertion(self) -> bool {
+        matches!(self, RelationTracking::Insertion | RelationTracking::All)
+    }
+
+    pub const fn tracks_deletion(self) -> bool {
+        matches!(self, RelationTracking::Deletion | RelationTracking::All)
+    }
+}
+
+/// Remembers which tracking data a consumer already saw, see `GetRelation::inserted_since`.
+///
+/// Unlike systems, views borrowed from the `World` directly don't know when they were last borrowed.
+/// A cursor can be kept around and passed to each new view instead.
+///
+/// Events are counted per relation storage, a cursor follows a single relation type.
+/// Events recorded after the cursor moved are yielded next time, even with the same view.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TrackingCursor {
+    insertion: u64,
+    deletion: u64,
+    retargeting: u64,
+}
+
+impl TrackingCursor {
+    /// Creates a cursor that hasn't seen anything yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// How long tracking data is kept, see `Relation::RETENTION`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Retention {
+    /// Tracking data is kept until it's cleared, by `RelationViewMut` or by the `World`.
+    Forever,
+    /// Tracking data older than this many timestamps is dropped whenever a `RelationViewMut` is borrowed
+    /// and when the `World` clears inserted components.
+    MaxAge(u32),
+}
+
 /// Indicates that a `struct` or `enum` is used as a relation type.
+///
+/// Besides the mode, constraints checked on every insertion can be declared.
+/// Each violated constraint is reported with its own `InsertError` variant.
 pub trait Relation: Send + Sync + 'static + Sized {
     type Mode: RelationMode + Send + Sync + 'static;
 
     const ACYCLIC: bool = true;
+    /// What happens to relations of an entity with itself.
+    ///
+    /// Defaults to `SelfLoops::Reject` for acyclic relations and `SelfLoops::Allow` otherwise.
+    const SELF_LOOPS: SelfLoops = if Self::ACYCLIC {
+        SelfLoops::Reject
+    } else {
+        SelfLoops::Allow
+    };
+    /// Maximum number of outgoing relations per entity.
+    ///
+    /// Undirected relations count as both outgoing and incoming for both entities.
+    /// Has no effect when the mode is exclusive on the outgoing side.
+    const MAX_OUTGOING: Option<usize> = None;
+    /// Maximum number of incoming relations per entity.
+    ///
+    /// Undirected relations count as both outgoing and incoming for both entities.
+    /// Has no effect when the mode is exclusive on the incoming side.
+    const MAX_INCOMING: Option<usize> = None;
+    /// Rejects a relation from `a` to `b` if there is one from `b` to `a`.
+    ///
+    /// Only affects directed modes.
+    const SYMMETRIC_UNIQUE: bool = false;
+
+    /// Makes iteration order independent of the history of insertions and deletions.
+    ///
+    /// `get`, `get_incoming`, `get_outgoing` and the `visit_*` iterators yield entities sorted by `EntityId`.
+    /// `inserted`, `deleted` and `retargeted` yield relations in the order of the events,
+    /// even after deletions. This costs a sort per query and an `O(n)` removal from the tracking data.
+    const DETERMINISTIC: bool = false;
+    /// Keeps `HasOutgoing<Self>` and `HasIncoming<Self>` marker components on entities with relations.
+    ///
+    /// `RelationViewMut` then also borrows these components exclusively and updates them
+    /// when it's borrowed and dropped, or with `RelationViewMut::sync_components`.
+    ///
+    /// Deleting an entity from the `World` deletes its relations without access to other storages,
+    /// the markers of the entities it was related to are stale until the `sync_relation_components`
+    /// system runs or the next `RelationViewMut` is borrowed or dropped.
+    const MARKERS: bool = false;
+    /// Keeps a `Target<Self>` component on entities with an outgoing relation when the mode is
+    /// exclusive on the outgoing side, and a `Source<Self>` component on entities with an incoming
+    /// relation when the mode is exclusive on the incoming side.
+    ///
+    /// The components are updated like the markers of `Relation::MARKERS`.
+    /// After an entity is deleted from the `World`, the components of the entities it was related to
+    /// point to it until the `sync_relation_components` system runs or the next `RelationViewMut`
+    /// is borrowed or dropped.
+    const MIRROR: bool = false;
+    /// Which events are tracked.
+    ///
+    /// Events that aren't tracked cost nothing and are never yielded by `GetRelation`.
+    const TRACKING: RelationTracking = RelationTracking::All;
+    /// How long insertions, deletions and retargetings are tracked.
+    ///
+    /// Deleted relations keep their data until their deletion is dropped.
+    const RETENTION: Retention = Retention::Forever;
+
+    /// Custom check run before a relation is inserted, after all other constraints.
+    #[allow(unused_variables)]
+    fn validate<G>(view: &G, a: EntityId, b: EntityId, relation: &Self) -> Result<(), &'static str>
+    where
+        G: GetRelation<Self>,
+    {
+        Ok(())
+    }
 }
 
 /// Used to retrieve various information from a relation view.
@@ -130,51 +270,43 @@ where
     }
 
     fn is_inserted(&self, a: EntityId, b: EntityId) -> bool {
-        self.storage().insertion_data.get(&(a, b)).map_or_else(
-            || {
-                if !<R::Mode as RelationMode>::EdgeType::is_directed() {
-                    self.storage()
-                        .insertion_data
-                        .get(&(b, a))
-                        .map_or(false, |timestamp| {
-                            timestamp.is_within(self.last_insertion(), self.current())
-                        })
-                } else {
-                    false
-                }
-            },
-            |timestamp| timestamp.is_within(self.last_insertion(), self.current()),
-        )
+        self.storage()
+            .insertion_data
+            .get(&RelationStorage::<R>::key(a, b))
+            .map_or(false, |(timestamp, _)| {
+                timestamp.is_within(self.last_insertion(), self.current())
+            })
     }
 
+    /// Iterates inserted relations.
+    ///
+    /// Undirected relations are yielded with the smaller `EntityId` first.
+    /// Always empty if `Relation::TRACKING` doesn't track insertions.
     fn inserted(&self) -> Box<dyn Iterator<Item = (EntityId, EntityId)> + '_> {
         Box::new(
             self.storage()
                 .insertion_data
                 .iter()
-                .filter(|(_, timestamp)| timestamp.is_within(self.last_insertion(), self.current()))
+                .filter(|(_, (timestamp, _))| {
+                    timestamp.is_within(self.last_insertion(), self.current())
+                })
                 .map(|((a, b), _)| (*a, *b)),
         )
     }
 
     fn is_deleted(&self, a: EntityId, b: EntityId) -> bool {
-        self.storage().deletion_data.get(&(a, b)).map_or_else(
-            || {
-                if !<R::Mode as RelationMode>::EdgeType::is_directed() {
-                    self.storage()
-                        .deletion_data
-                        .get(&(b, a))
-                        .map_or(false, |timestamp| {
-                            timestamp.0.is_within(self.last_deletion(), self.current())
-                        })
-                } else {
-                    false
-                }
-            },
-            |timestamp| timestamp.0.is_within(self.last_deletion(), self.current()),
-        )
+        self.storage()
+            .deletion_data
+            .get(&RelationStorage::<R>::key(a, b))
+            .map_or(false, |(timestamp, _, _)| {
+                timestamp.is_within(self.last_deletion(), self.current())
+            })
     }
 
+    /// Iterates deleted relations along with their data.
+    ///
+    /// Undirected relations are yielded with the smaller `EntityId` first.
+    /// Always empty if `Relation::TRACKING` doesn't track deletions.
     fn deleted<'a>(&'a self) -> Box<dyn Iterator<Item = ((EntityId, EntityId), &'a R)> + 'a>
     where
         R: 'a,
@@ -183,10 +315,107 @@ where
             self.storage()
                 .deletion_data
                 .iter()
-                .filter(|(_, timestamp)| {
-                    timestamp.0.is_within(self.last_deletion(), self.current())
+                .filter(|(_, (timestamp, _, _))| {
+                    timestamp.is_within(self.last_deletion(), self.current())
                 })
-                .map(|((a, b), (_, r))| ((*a, *b), r)),
+                .map(|((a, b), (_, _, r))| ((*a, *b), r)),
+        )
+    }
+
+    /// Iterates relations inserted since `cursor` last saw all insertions.
+    ///
+    /// `cursor` only moves once the iterator is exhausted, up to the last insertion it saw.
+    /// Dropping the iterator early yields the same relations again next time.
+    fn inserted_since<'a>(
+        &'a self,
+        cursor: &'a mut TrackingCursor,
+    ) -> Box<dyn Iterator<Item = (EntityId, EntityId)> + 'a> {
+        let last = cursor.insertion;
+        let sequence = self.storage().sequence;
+
+        Box::new(
+            self.storage()
+                .insertion_data
+                .iter()
+                .filter(move |(_, (_, s))| *s > last)
+                .map(|((a, b), _)| (*a, *b))
+                .chain(std::iter::from_fn(move || {
+                    cursor.insertion = sequence;
+                    None
+                })),
+        )
+    }
+
+    /// Iterates relations deleted since `cursor` last saw all deletions.
+    ///
+    /// `cursor` moves the same way as with `inserted_since`.
+    fn deleted_since<'a>(
+        &'a self,
+        cursor: &'a mut TrackingCursor,
+    ) -> Box<dyn Iterator<Item = ((EntityId, EntityId), &'a R)> + 'a>
+    where
+        R: 'a,
+    {
+        let last = cursor.deletion;
+        let sequence = self.storage().sequence;
+
+        Box::new(
+            self.storage()
+                .deletion_data
+                .iter()
+                .filter(move |(_, (_, s, _))| *s > last)
+                .map(|((a, b), (_, _, r))| ((*a, *b), r))
+                .chain(std::iter::from_fn(move || {
+                    cursor.deletion = sequence;
+                    None
+                })),
+        )
+    }
+
+    /// Iterates relations retargeted since `cursor` last saw all retargetings, like `retargeted`.
+    ///
+    /// `cursor` moves the same way as with `inserted_since`.
+    fn retargeted_since<'a>(
+        &'a self,
+        cursor: &'a mut TrackingCursor,
+    ) -> Box<dyn Iterator<Item = (EntityId, EntityId, EntityId)> + 'a> {
+        let last = cursor.retargeting;
+        let sequence = self.storage().sequence;
+
+        Box::new(
+            self.storage()
+                .retargeting_data
+                .iter()
+                .filter(move |(_, (_, s, _, _))| *s > last)
+                .map(|(&(x, y), &(_, _, a, old_b))| (a, old_b, if x == a { y } else { x }))
+                .chain(std::iter::from_fn(move || {
+                    cursor.retargeting = sequence;
+                    None
+                })),
+        )
+    }
+
+    fn is_retargeted(&self, a: EntityId, b: EntityId) -> bool {
+        self.storage()
+            .retargeting_data
+            .get(&RelationStorage::<R>::key(a, b))
+            .map_or(false, |(timestamp, _, _, _)| {
+                timestamp.is_within(self.last_insertion(), self.current())
+            })
+    }
+
+    /// Iterates relations moved with `RelationViewMut::retarget` as `(a, old_b, new_b)`.
+    ///
+    /// Always empty if `Relation::TRACKING` doesn't track insertions.
+    fn retargeted(&self) -> Box<dyn Iterator<Item = (EntityId, EntityId, EntityId)> + '_> {
+        Box::new(
+            self.storage()
+                .retargeting_data
+                .iter()
+                .filter(|(_, (timestamp, _, _, _))| {
+                    timestamp.is_within(self.last_insertion(), self.current())
+                })
+                .map(|(&(x, y), &(_, _, a, old_b))| (a, old_b, if x == a { y } else { x })),
         )
     }
 
@@ -197,4 +426,250 @@ where
     fn visit_breadth_first(&self, entity: EntityId) -> BreadthFirstIter<'_, R> {
         BreadthFirstIter::new(&self.storage().graph, entity)
     }
+
+    /// Restricts the relations to the entities accepted by `filter`, without copying them.
+    fn filtered<'a, F>(&'a self, filter: F) -> FilteredRelation<'a, R>
+    where
+        F: Fn(EntityId) -> bool + 'a,
+    {
+        FilteredRelation::new(self.storage(), filter)
+    }
+
+    /// Restricts the relations to the entities with a `C` component, see `filtered`.
+    fn with_component<'a, C>(&'a self, view: &'a View<'_, C>) -> FilteredRelation<'a, R>
+    where
+        C: Component,
+    {
+        self.filtered(move |entity| view.contains(entity))
+    }
+
+    /// Iterates the entities `entity` can be reached from by following relations, closest first.
+    ///
+    /// `Tree` stores relations from the child to the parent, its ancestors are the parents up to the root.
+    /// Only follows a single chain of relations when entities have at most one parent.
+    /// Undirected relations yield every entity connected to `entity`.
+    fn ancestors(&self, entity: EntityId) -> LineageIter<'_, R> {
+        LineageIter::new(&self.storage().graph, entity, RelationStorage::<R>::up())
+    }
+
+    /// Iterates the entities that can be reached from `entity` by following relations, closest first.
+    ///
+    /// `Tree` stores relations from the child to the parent, its descendants are the whole subtree.
+    /// Only follows a single chain of relations when entities have at most one child.
+    /// Undirected relations yield every entity connected to `entity`.
+    fn descendants(&self, entity: EntityId) -> LineageIter<'_, R> {
+        LineageIter::new(&self.storage().graph, entity, RelationStorage::<R>::down())
+    }
+
+    /// Returns `true` if `b` can be reached from `a` by following relations.
+    ///
+    /// An entity can always reach itself.
+    /// For modes exclusive on one side, only the chain of relations on that side is walked.
+    fn is_reachable(&self, a: EntityId, b: EntityId) -> bool {
+        if a == b {
+            return true;
+        }
+
+        let directed = <R::Mode as RelationMode>::EdgeType::is_directed();
+        let graph = &self.storage().graph;
+
+        // follows the relations as stored, whichever way parents and children are
+        if directed && R::Mode::is_exclusive_incoming() {
+            LineageIter::<R>::new(graph, b, petgraph::Direction::Incoming).any(|entity| entity == a)
+        } else if directed && R::Mode::is_exclusive_outgoing() {
+            LineageIter::<R>::new(graph, a, petgraph::Direction::Outgoing).any(|entity| entity == b)
+        } else {
+            self.storage().has_path(a, b)
+        }
+    }
+
+    /// Returns `true` if `a` and `b` are connected, no matter the direction of the relations.
+    fn path_exists(&self, a: EntityId, b: EntityId) -> bool {
+        if <R::Mode as RelationMode>::EdgeType::is_directed() {
+            self.storage().is_connected(a, b)
+        } else {
+            self.is_reachable(a, b)
+        }
+    }
+
+    /// Returns the closest entity that is an ancestor of both `a` and `b`, `a` and `b` included.
+    ///
+    /// When entities can have several parents, they can have multiple
+    /// lowest common ancestors, the one closest to `b` is returned.
+    fn lowest_common_ancestor(&self, a: EntityId, b: EntityId) -> Option<EntityId> {
+        let ancestors = std::iter::once(a)
+            .chain(self.ancestors(a))
+            .collect::<HashSet<_>>();
+
+        std::iter::once(b)
+            .chain(self.ancestors(b))
+            .find(|entity| ancestors.contains(entity))
+    }
+
+    /// Iterates the entities with relations going out but none coming in.
+    ///
+    /// For `Tree` these are the entities with children but without a parent.
+    /// Undirected relations have no roots.
+    fn roots(&self) -> Box<dyn Iterator<Item = EntityId> + '_> {
+        sorted_entities::<R>(self.storage().extremities.roots.iter().copied())
+    }
+
+    /// Iterates the entities with relations coming in but none going out.
+    ///
+    /// For `Tree` these are the entities with a parent but without children.
+    /// For undirected relations these are the entities with a single relation.
+    fn leaves(&self) -> Box<dyn Iterator<Item = EntityId> + '_> {
+        sorted_entities::<R>(self.storage().extremities.leaves.iter().copied())
+    }
+
+    /// Iterates the entities that had relations but don't anymore.
+    fn isolated(&self) -> Box<dyn Iterator<Item = EntityId> + '_> {
+        let storage = self.storage();
+
+        sorted_entities::<R>(
+            storage
+                .extremities
+                .isolated
+                .iter()
+                .copied()
+                // older generations are dropped when the index is reused
+                .filter(move |&entity| storage.graph.contains_node(entity)),
+        )
+    }
+
+    /// Returns `true` if `entity` has no children.
+    fn is_leaf(&self, entity: EntityId) -> bool
+    where
+        R::Mode: TreeMode,
+    {
+        !self
+            .storage()
+            .has_neighbor(entity, petgraph::Direction::Incoming)
+    }
+
+    /// Number of parents above `entity`, roots have a depth of 0.
+    fn depth(&self, entity: EntityId) -> usize
+    where
+        R::Mode: TreeMode,
+    {
+        self.ancestors(entity).count()
+    }
+
+    /// Number of entities in the subtree of `entity`, itself included.
+    fn subtree_size(&self, entity: EntityId) -> usize
+    where
+        R::Mode: TreeMode,
+    {
+        1 + self.descendants(entity).count()
+    }
+
+    /// Groups of entities that can all reach each other by following relations.
+    ///
+    /// Entities that are not part of any cycle are alone in their group.
+    /// For undirected relations the groups are the connected entities.
+    fn strongly_connected_components(&self) -> Vec<Vec<EntityId>> {
+        self.storage().strongly_connected_components()
+    }
+
+    /// Returns a cycle as the entities along it, the last one relating to the first one.
+    ///
+    /// Always `None` for undirected relations.
+    fn find_cycle(&self) -> Option<Vec<EntityId>> {
+        self.storage().find_cycle()
+    }
+
+    /// Returns every cycle going through `entity`, each starting with `entity`.
+    ///
+    /// The number of cycles can grow exponentially with the number of relations.
+    /// Always empty for undirected relations.
+    fn cycles_through(&self, entity: EntityId) -> Vec<Vec<EntityId>> {
+        self.storage().cycles_through(entity)
+    }
+
+    /// Computes a value for each entity below `roots`, from the value of its parent.
+    ///
+    /// Roots start with `init`, then `f(parent_value, child, relation)` is called for each relation,
+    /// from parents to children in topological order. Parents are the `a` of the relations,
+    /// or the `b` for `Tree`. Undirected relations are followed away from the roots.
+    /// An entity related to by several entities keeps the value computed from the last one.
+    /// Entities on cycles and the ones only reachable through them are left out.
+    fn propagate_down<T, F>(
+        &self,
+        roots: impl IntoIterator<Item = EntityId>,
+        init: T,
+        f: F,
+    ) -> HashMap<EntityId, T>
+    where
+        T: Clone,
+        F: FnMut(&T, EntityId, &R) -> T,
+    {
+        let starts = roots.into_iter().map(|root| (root, init.clone())).collect();
+
+        self.storage().propagate(starts, f)
+    }
+
+    /// Updates `values`, computed by `propagate_down`, below the relations returned by `inserted`,
+    /// `retargeted` and `deleted`.
+    ///
+    /// The children of these relations and everything below them are recomputed,
+    /// starting from the values of their other parents.
+    /// Entities left without any parent that has a value keep their previous value,
+    /// deleted entities keep theirs too. Undirected relations are not supported.
+    fn propagate_down_inserted<T, F>(&self, values: &mut HashMap<EntityId, T>, f: F)
+    where
+        T: Clone,
+        F: FnMut(&T, EntityId, &R) -> T,
+    {
+        if !<R::Mode as RelationMode>::EdgeType::is_directed() {
+            return;
+        }
+
+        let child_to_parent = R::Mode::is_child_to_parent();
+        let changed = self
+            .inserted()
+            .map(|(a, b)| if child_to_parent { a } else { b })
+            .chain(self.retargeted().flat_map(|(a, old_b, new_b)| {
+                if child_to_parent {
+                    vec![a]
+                } else {
+                    vec![old_b, new_b]
+                }
+            }))
+            .chain(
+                self.deleted()
+                    .map(|((a, b), _)| if child_to_parent { a } else { b }),
+            )
+            .collect();
+
+        self.storage().repropagate(values, changed, f);
+    }
+
+    /// Computes a value for each entity from the values of its children, leaves first.
+    ///
+    /// `f(children, entity)` gets the values of the children of `entity` along with the relations,
+    /// children are the `b` of the relations, or the `a` for `Tree`.
+    /// Entities on cycles are left out and so are the relations to them.
+    /// Undirected relations have no direction to fold along, the map is always empty for them.
+    fn fold_up<T, F>(&self, f: F) -> HashMap<EntityId, T>
+    where
+        F: FnMut(&mut dyn Iterator<Item = (&T, &R)>, EntityId) -> T,
+    {
+        self.storage().fold_up(f)
+    }
+}
+
+/// Sorts `entities` for deterministic relations.
+fn sorted_entities<'a, R>(
+    entities: impl Iterator<Item = EntityId> + 'a,
+) -> Box<dyn Iterator<Item = EntityId> + 'a>
+where
+    R: Relation,
+{
+    if R::DETERMINISTIC {
+        let mut entities = entities.collect::<Vec<_>>();
+        entities.sort_unstable();
+        Box::new(entities.into_iter())
+    } else {
+        Box::new(entities)
+    }
 }
@@ -0,0 +1,197 @@
+use shipyard::{AllStorages, EntityId};
+
+use crate::{storage::StagedRelation, InsertError, Relation, RelationViewMut};
+
+/// Relation from a newly added entity to `target`.
+///
+/// Used with `RelationExt::add_entity_with_relations`.
+pub struct RelationTo<R>(pub EntityId, pub R);
+
+/// Relation from `source` to a newly added entity.
+///
+/// Used with `RelationExt::add_entity_with_relations`.
+pub struct RelationFrom<R>(pub EntityId, pub R);
+
+/// Relations that can be inserted together with a new entity.
+///
+/// Implemented for `RelationTo`, `RelationFrom` and tuples of them.
+pub trait RelationBundle: Sized {
+    #[doc(hidden)]
+    type Staged;
+
+    /// Checks the relations one after the other, each as if the previous ones were inserted.
+    ///
+    /// If one of them is rejected, the ones already staged are unstaged again.
+    #[doc(hidden)]
+    fn stage(
+        self,
+        entity: EntityId,
+        all_storages: &AllStorages,
+    ) -> Result<Self::Staged, InsertError>;
+
+    /// Unstages the relations in reverse order.
+    #[doc(hidden)]
+    fn unstage(staged: &mut Self::Staged, all_storages: &AllStorages);
+
+    #[doc(hidden)]
+    fn check_on_insert(
+        staged: &Self::Staged,
+        all_storages: &AllStorages,
+    ) -> Result<(), InsertError>;
+
+    #[doc(hidden)]
+    fn insert_staged(staged: Self::Staged, all_storages: &AllStorages);
+
+    /// Inserts the relations for `entity` if all of them are accepted, otherwise nothing changes.
+    ///
+    /// `on_insert` is asked once per relation, after all the other checks passed.
+    fn insert_relations(
+        self,
+        entity: EntityId,
+        all_storages: &AllStorages,
+    ) -> Result<(), InsertError> {
+        let mut staged = self.stage(entity, all_storages)?;
+        Self::unstage(&mut staged, all_storages);
+        Self::check_on_insert(&staged, all_storages)?;
+        Self::insert_staged(staged, all_storages);
+
+        Ok(())
+    }
+}
+
+impl<R> RelationBundle for RelationTo<R>
+where
+    R: Relation,
+{
+    type Staged = StagedRelation<R>;
+
+    fn stage(
+        self,
+        entity: EntityId,
+        all_storages: &AllStorages,
+    ) -> Result<Self::Staged, InsertError> {
+        let mut relation_view = all_storages.borrow::<RelationViewMut<R>>().unwrap();
+        relation_view.stage(entity, self.0, self.1)
+    }
+
+    fn unstage(staged: &mut Self::Staged, all_storages: &AllStorages) {
+        let mut relation_view = all_storages.borrow::<RelationViewMut<R>>().unwrap();
+        relation_view.unstage(staged);
+    }
+
+    fn check_on_insert(
+        staged: &Self::Staged,
+        all_storages: &AllStorages,
+    ) -> Result<(), InsertError> {
+        let relation_view = all_storages.borrow::<RelationViewMut<R>>().unwrap();
+        relation_view.storage.check_on_insert_staged(staged)
+    }
+
+    fn insert_staged(staged: Self::Staged, all_storages: &AllStorages) {
+        let mut relation_view = all_storages.borrow::<RelationViewMut<R>>().unwrap();
+        relation_view.insert_staged(staged);
+    }
+}
+
+impl<R> RelationBundle for RelationFrom<R>
+where
+    R: Relation,
+{
+    type Staged = StagedRelation<R>;
+
+    fn stage(
+        self,
+        entity: EntityId,
+        all_storages: &AllStorages,
+    ) -> Result<Self::Staged, InsertError> {
+        let mut relation_view = all_storages.borrow::<RelationViewMut<R>>().unwrap();
+        relation_view.stage(self.0, entity, self.1)
+    }
+
+    fn unstage(staged: &mut Self::Staged, all_storages: &AllStorages) {
+        let mut relation_view = all_storages.borrow::<RelationViewMut<R>>().unwrap();
+        relation_view.unstage(staged);
+    }
+
+    fn check_on_insert(
+        staged: &Self::Staged,
+        all_storages: &AllStorages,
+    ) -> Result<(), InsertError> {
+        let relation_view = all_storages.borrow::<RelationViewMut<R>>().unwrap();
+        relation_view.storage.check_on_insert_staged(staged)
+    }
+
+    fn insert_staged(staged: Self::Staged, all_storages: &AllStorages) {
+        let mut relation_view = all_storages.borrow::<RelationViewMut<R>>().unwrap();
+        relation_view.insert_staged(staged);
+    }
+}
+
+macro_rules! impl_relation_bundle {
+    ($(($bundle: ident, $index: tt))+; $(($rev_bundle: ident, $rev_index: tt))+) => {
+        impl<$($bundle),+> RelationBundle for ($($bundle,)+)
+        where
+            $($bundle: RelationBundle,)+
+        {
+            type Staged = ($(Option<$bundle::Staged>,)+);
+
+            fn stage(
+                self,
+                entity: EntityId,
+                all_storages: &AllStorages,
+            ) -> Result<Self::Staged, InsertError> {
+                let mut staged: Self::Staged = Default::default();
+
+                $(
+                    match self.$index.stage(entity, all_storages) {
+                        Ok(relation) => staged.$index = Some(relation),
+                        Err(err) => {
+                            Self::unstage(&mut staged, all_storages);
+                            return Err(err);
+                        }
+                    }
+                )+
+
+                Ok(staged)
+            }
+
+            fn unstage(staged: &mut Self::Staged, all_storages: &AllStorages) {
+                $(
+                    if let Some(relation) = &mut staged.$rev_index {
+                        $rev_bundle::unstage(relation, all_storages);
+                    }
+                )+
+            }
+
+            fn check_on_insert(
+                staged: &Self::Staged,
+                all_storages: &AllStorages,
+            ) -> Result<(), InsertError> {
+                $(
+                    if let Some(relation) = &staged.$index {
+                        $bundle::check_on_insert(relation, all_storages)?;
+                    }
+                )+
+
+                Ok(())
+            }
+
+            fn insert_staged(staged: Self::Staged, all_storages: &AllStorages) {
+                $(
+                    if let Some(relation) = staged.$index {
+                        $bundle::insert_staged(relation, all_storages);
+                    }
+                )+
+            }
+        }
+    };
+}
+
+impl_relation_bundle![(A, 0); (A, 0)];
+impl_relation_bundle![(A, 0)(B, 1); (B, 1)(A, 0)];
+impl_relation_bundle![(A, 0)(B, 1)(C, 2); (C, 2)(B, 1)(A, 0)];
+impl_relation_bundle![(A, 0)(B, 1)(C, 2)(D, 3); (D, 3)(C, 2)(B, 1)(A, 0)];
+impl_relation_bundle![(A, 0)(B, 1)(C, 2)(D, 3)(E, 4); (E, 4)(D, 3)(C, 2)(B, 1)(A, 0)];
+impl_relation_bundle![(A, 0)(B, 1)(C, 2)(D, 3)(E, 4)(F, 5); (F, 5)(E, 4)(D, 3)(C, 2)(B, 1)(A, 0)];
+impl_relation_bundle![(A, 0)(B, 1)(C, 2)(D, 3)(E, 4)(F, 5)(G, 6); (G, 6)(F, 5)(E, 4)(D, 3)(C, 2)(B, 1)(A, 0)];
+impl_relation_bundle![(A, 0)(B, 1)(C, 2)(D, 3)(E, 4)(F, 5)(G, 6)(H, 7); (H, 7)(G, 6)(F, 5)(E, 4)(D, 3)(C, 2)(B, 1)(A, 0)];
@@ -1,6 +1,6 @@
-use shipyard::{AllStorages, EntityId, World};
+use shipyard::{AllStorages, EntityId, TupleAddComponent, World};
 
-use crate::{InsertError, Relation, RelationViewMut};
+use crate::{InsertError, Relation, RelationBundle, RelationViewMut};
 
 /// Extension trait for adding relations using `shipyard::World` or `shipyard::AllStorages`.
 pub trait RelationExt {
@@ -11,6 +11,26 @@ pub trait RelationExt {
     fn add_relation_unchecked<R>(&self, a: EntityId, b: EntityId, relation: R)
     where
         R: Relation;
+
+    /// Runs the checks of `add_relation` without asking `on_insert` nor inserting the relation.
+    fn check_relation<R>(&self, a: EntityId, b: EntityId, relation: &R) -> Result<(), InsertError>
+    where
+        R: Relation;
+
+    /// Adds a new entity with `component` and inserts `relations` for it.
+    ///
+    /// Each relation is checked as if the previous ones were inserted, then `on_insert` is asked
+    /// once for each of them.
+    /// If one of them is rejected, no relation is inserted, the entity is deleted again
+    /// and the error is returned.
+    fn add_entity_with_relations<C, B>(
+        &mut self,
+        component: C,
+        relations: B,
+    ) -> Result<EntityId, InsertError>
+    where
+        C: TupleAddComponent,
+        B: RelationBundle;
 }
 
 impl RelationExt for World {
@@ -29,6 +49,28 @@ impl RelationExt for World {
         let mut relation_view = self.borrow::<RelationViewMut<R>>().unwrap();
         relation_view.insert(a, b, relation);
     }
+
+    fn check_relation<R>(&self, a: EntityId, b: EntityId, relation: &R) -> Result<(), InsertError>
+    where
+        R: Relation,
+    {
+        let relation_view = self.borrow::<RelationViewMut<R>>().unwrap();
+        relation_view.check_insert(a, b, relation)
+    }
+
+    fn add_entity_with_relations<C, B>(
+        &mut self,
+        component: C,
+        relations: B,
+    ) -> Result<EntityId, InsertError>
+    where
+        C: TupleAddComponent,
+        B: RelationBundle,
+    {
+        self.all_storages_mut()
+            .unwrap()
+            .add_entity_with_relations(component, relations)
+    }
 }
 
 impl RelationExt for AllStorages {
@@ -47,4 +89,31 @@ impl RelationExt for AllStorages {
         let mut relation_view = self.borrow::<RelationViewMut<R>>().unwrap();
         relation_view.insert(a, b, relation);
     }
+
+    fn check_relation<R>(&self, a: EntityId, b: EntityId, relation: &R) -> Result<(), InsertError>
+    where
+        R: Relation,
+    {
+        let relation_view = self.borrow::<RelationViewMut<R>>().unwrap();
+        relation_view.check_insert(a, b, relation)
+    }
+
+    fn add_entity_with_relations<C, B>(
+        &mut self,
+        component: C,
+        relations: B,
+    ) -> Result<EntityId, InsertError>
+    where
+        C: TupleAddComponent,
+        B: RelationBundle,
+    {
+        let entity = self.add_entity(component);
+
+        if let Err(err) = relations.insert_relations(entity, self) {
+            self.delete_entity(entity);
+            return Err(err);
+        }
+
+        Ok(entity)
+    }
 }
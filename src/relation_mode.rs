@@ -1,12 +1,31 @@
+use std::marker::PhantomData;
+
 use petgraph::{prelude::GraphMap, EdgeType};
 use shipyard::EntityId;
 
+use crate::{
+    adjacency::{Adjacency, SparseAdjacency},
+    Relation,
+};
+
 pub enum Directed {}
 pub enum DirectedExclusive {}
 pub enum DirectedExclusiveIncoming {}
 pub enum DirectedExclusiveOutgoing {}
 pub enum Undirected {}
 pub enum UndirectedExclusive {}
+/// Hierarchy where relations go from a child to its only parent.
+///
+/// Always acyclic, no matter `Relation::ACYCLIC`, and self-loops are always rejected.
+/// Preventing cycles only walks up the parents of the new parent.
+/// Multiple roots are allowed, making it a forest.
+pub enum Tree {}
+
+/// Stores the relations of mode `M` in a `SparseAdjacency` instead of a `GraphMap`.
+///
+/// Entities are looked up by their index instead of being hashed,
+/// which is faster when each entity only has a few relations, like in hierarchies.
+pub struct SparseSet<M>(PhantomData<M>);
 
 mod sealed {
     pub trait Sealed {}
@@ -16,8 +35,17 @@ mod sealed {
     impl Sealed for super::DirectedExclusiveOutgoing {}
     impl Sealed for super::Undirected {}
     impl Sealed for super::UndirectedExclusive {}
+    impl Sealed for super::Tree {}
+    impl<M> Sealed for super::SparseSet<M> where M: super::RelationMode {}
 }
 
+/// Modes storing a hierarchy, enables the tree accessors of `GetRelation`.
+#[doc(hidden)]
+pub trait TreeMode: RelationMode {}
+
+impl TreeMode for Tree {}
+impl<M> TreeMode for SparseSet<M> where M: TreeMode {}
+
 #[doc(hidden)]
 pub trait RelationMode: sealed::Sealed {
     type EdgeType: EdgeType + Send + Sync + 'static;
@@ -27,32 +55,123 @@ pub trait RelationMode: sealed::Sealed {
     type GetOutgoing<'a, R>
     where
         R: 'a;
+    type GetIncomingMut<'a, R>
+    where
+        R: 'a;
+    type GetOutgoingMut<'a, R>
+    where
+        R: 'a;
+    type Adjacency<R>: Adjacency<R>
+    where
+        R: Relation;
 
     fn is_exclusive_incoming() -> bool;
     fn is_exclusive_outgoing() -> bool;
+    /// The mode prevents cycles on its own.
+    fn is_acyclic() -> bool {
+        false
+    }
+    /// Relations go from a child to its parent, flipping roots and leaves.
+    fn is_child_to_parent() -> bool {
+        false
+    }
 
-    fn get<R>(
-        graph: &GraphMap<EntityId, R, Self::EdgeType>,
-        entity: EntityId,
-    ) -> Self::GetOutgoing<'_, R> {
+    fn get<R, A>(graph: &A, entity: EntityId) -> Self::GetOutgoing<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
         Self::get_outgoing(graph, entity)
     }
 
-    fn get_incoming<R>(
-        graph: &GraphMap<EntityId, R, Self::EdgeType>,
-        entity: EntityId,
-    ) -> Self::GetIncoming<'_, R>;
+    fn get_incoming<R, A>(graph: &A, entity: EntityId) -> Self::GetIncoming<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>;
+
+    fn get_outgoing<R, A>(graph: &A, entity: EntityId) -> Self::GetOutgoing<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>;
+
+    fn get_incoming_mut<R, A>(graph: &mut A, entity: EntityId) -> Self::GetIncomingMut<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>;
+
+    fn get_outgoing_mut<R, A>(graph: &mut A, entity: EntityId) -> Self::GetOutgoingMut<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>;
+}
+
+/// Sorts `iter` by `EntityId` for deterministic relations.
+fn sorted<'a, R, T>(
+    iter: impl Iterator<Item = (EntityId, T)> + 'a,
+) -> Box<dyn Iterator<Item = (EntityId, T)> + 'a>
+where
+    R: Relation,
+    T: 'a,
+{
+    if R::DETERMINISTIC {
+        let mut items = iter.collect::<Vec<_>>();
+        items.sort_unstable_by_key(|(e, _)| *e);
+        Box::new(items.into_iter())
+    } else {
+        Box::new(iter)
+    }
+}
+
+fn edges<R, A>(
+    graph: &A,
+    entity: EntityId,
+    direction: petgraph::Direction,
+) -> Box<dyn Iterator<Item = (EntityId, &R)> + '_>
+where
+    R: Relation,
+    A: Adjacency<R>,
+{
+    sorted::<R, _>(graph.edges_directed(entity, direction))
+}
+
+fn edges_mut<R, A>(
+    graph: &mut A,
+    entity: EntityId,
+    direction: petgraph::Direction,
+) -> Box<dyn Iterator<Item = (EntityId, &mut R)> + '_>
+where
+    R: Relation,
+    A: Adjacency<R>,
+{
+    sorted::<R, _>(graph.edges_directed_mut(entity, direction))
+}
 
-    fn get_outgoing<R>(
-        graph: &GraphMap<EntityId, R, Self::EdgeType>,
-        entity: EntityId,
-    ) -> Self::GetOutgoing<'_, R>;
+fn edge_mut<R, A>(
+    graph: &mut A,
+    entity: EntityId,
+    direction: petgraph::Direction,
+) -> Option<(EntityId, &mut R)>
+where
+    R: Relation,
+    A: Adjacency<R>,
+{
+    // `edges_directed_mut` can scan all relations, looking the single one up by its ends doesn't
+    let other = graph.neighbors_directed(entity, direction).next()?;
+    let relation = match direction {
+        petgraph::Direction::Outgoing => graph.edge_weight_mut(entity, other),
+        petgraph::Direction::Incoming => graph.edge_weight_mut(other, entity),
+    }?;
+
+    Some((other, relation))
 }
 
 impl RelationMode for Directed {
     type EdgeType = petgraph::Directed;
     type GetIncoming<'a, R> = Box<dyn Iterator<Item = (EntityId, &'a R)> + 'a> where R: 'a;
     type GetOutgoing<'a, R> = Box<dyn Iterator<Item = (EntityId, &'a R)> + 'a> where R: 'a;
+    type GetIncomingMut<'a, R> = Box<dyn Iterator<Item = (EntityId, &'a mut R)> + 'a> where R: 'a;
+    type GetOutgoingMut<'a, R> = Box<dyn Iterator<Item = (EntityId, &'a mut R)> + 'a> where R: 'a;
+    type Adjacency<R> = GraphMap<EntityId, R, Self::EdgeType> where R: Relation;
 
     fn is_exclusive_incoming() -> bool {
         false
@@ -62,26 +181,36 @@ impl RelationMode for Directed {
         false
     }
 
-    fn get_incoming<R>(
-        graph: &GraphMap<EntityId, R, Self::EdgeType>,
-        entity: EntityId,
-    ) -> Self::GetIncoming<'_, R> {
-        Box::new(
-            graph
-                .edges_directed(entity, petgraph::Direction::Incoming)
-                .map(|(e, _, r)| (e, r)),
-        )
+    fn get_incoming<R, A>(graph: &A, entity: EntityId) -> Self::GetIncoming<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        edges(graph, entity, petgraph::Direction::Incoming)
+    }
+
+    fn get_outgoing<R, A>(graph: &A, entity: EntityId) -> Self::GetOutgoing<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        edges(graph, entity, petgraph::Direction::Outgoing)
+    }
+
+    fn get_incoming_mut<R, A>(graph: &mut A, entity: EntityId) -> Self::GetIncomingMut<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        edges_mut(graph, entity, petgraph::Direction::Incoming)
     }
 
-    fn get_outgoing<R>(
-        graph: &GraphMap<EntityId, R, Self::EdgeType>,
-        entity: EntityId,
-    ) -> Self::GetOutgoing<'_, R> {
-        Box::new(
-            graph
-                .edges_directed(entity, petgraph::Direction::Outgoing)
-                .map(|(_, e, r)| (e, r)),
-        )
+    fn get_outgoing_mut<R, A>(graph: &mut A, entity: EntityId) -> Self::GetOutgoingMut<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        edges_mut(graph, entity, petgraph::Direction::Outgoing)
     }
 }
 
@@ -89,6 +218,9 @@ impl RelationMode for DirectedExclusive {
     type EdgeType = petgraph::Directed;
     type GetIncoming<'a, R> = Option<(EntityId, &'a R)> where R: 'a;
     type GetOutgoing<'a, R> = Option<(EntityId, &'a R)> where R: 'a;
+    type GetIncomingMut<'a, R> = Option<(EntityId, &'a mut R)> where R: 'a;
+    type GetOutgoingMut<'a, R> = Option<(EntityId, &'a mut R)> where R: 'a;
+    type Adjacency<R> = GraphMap<EntityId, R, Self::EdgeType> where R: Relation;
 
     fn is_exclusive_incoming() -> bool {
         true
@@ -98,31 +230,50 @@ impl RelationMode for DirectedExclusive {
         true
     }
 
-    fn get_incoming<R>(
-        graph: &GraphMap<EntityId, R, Self::EdgeType>,
-        entity: EntityId,
-    ) -> Self::GetIncoming<'_, R> {
+    fn get_incoming<R, A>(graph: &A, entity: EntityId) -> Self::GetIncoming<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
         graph
             .edges_directed(entity, petgraph::Direction::Incoming)
-            .map(|(e, _, r)| (e, r))
             .next()
     }
 
-    fn get_outgoing<R>(
-        graph: &GraphMap<EntityId, R, Self::EdgeType>,
-        entity: EntityId,
-    ) -> Self::GetOutgoing<'_, R> {
+    fn get_outgoing<R, A>(graph: &A, entity: EntityId) -> Self::GetOutgoing<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
         graph
             .edges_directed(entity, petgraph::Direction::Outgoing)
-            .map(|(_, e, r)| (e, r))
             .next()
     }
+
+    fn get_incoming_mut<R, A>(graph: &mut A, entity: EntityId) -> Self::GetIncomingMut<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        edge_mut(graph, entity, petgraph::Direction::Incoming)
+    }
+
+    fn get_outgoing_mut<R, A>(graph: &mut A, entity: EntityId) -> Self::GetOutgoingMut<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        edge_mut(graph, entity, petgraph::Direction::Outgoing)
+    }
 }
 
 impl RelationMode for DirectedExclusiveIncoming {
     type EdgeType = petgraph::Directed;
     type GetIncoming<'a, R> = Option<(EntityId, &'a R)> where R: 'a;
     type GetOutgoing<'a, R> = Box<dyn Iterator<Item = (EntityId, &'a R)> + 'a> where R: 'a;
+    type GetIncomingMut<'a, R> = Option<(EntityId, &'a mut R)> where R: 'a;
+    type GetOutgoingMut<'a, R> = Box<dyn Iterator<Item = (EntityId, &'a mut R)> + 'a> where R: 'a;
+    type Adjacency<R> = GraphMap<EntityId, R, Self::EdgeType> where R: Relation;
 
     fn is_exclusive_incoming() -> bool {
         true
@@ -132,32 +283,97 @@ impl RelationMode for DirectedExclusiveIncoming {
         false
     }
 
-    fn get_incoming<R>(
-        graph: &GraphMap<EntityId, R, Self::EdgeType>,
-        entity: EntityId,
-    ) -> Self::GetIncoming<'_, R> {
+    fn get_incoming<R, A>(graph: &A, entity: EntityId) -> Self::GetIncoming<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        graph.edges_directed(entity, petgraph::Incoming).next()
+    }
+
+    fn get_outgoing<R, A>(graph: &A, entity: EntityId) -> Self::GetOutgoing<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        edges(graph, entity, petgraph::Direction::Outgoing)
+    }
+
+    fn get_incoming_mut<R, A>(graph: &mut A, entity: EntityId) -> Self::GetIncomingMut<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        edge_mut(graph, entity, petgraph::Direction::Incoming)
+    }
+
+    fn get_outgoing_mut<R, A>(graph: &mut A, entity: EntityId) -> Self::GetOutgoingMut<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        edges_mut(graph, entity, petgraph::Direction::Outgoing)
+    }
+}
+
+impl RelationMode for DirectedExclusiveOutgoing {
+    type EdgeType = petgraph::Directed;
+    type GetIncoming<'a, R> = Box<dyn Iterator<Item = (EntityId, &'a R)> + 'a> where R: 'a;
+    type GetOutgoing<'a, R> = Option<(EntityId, &'a R)> where R: 'a;
+    type GetIncomingMut<'a, R> = Box<dyn Iterator<Item = (EntityId, &'a mut R)> + 'a> where R: 'a;
+    type GetOutgoingMut<'a, R> = Option<(EntityId, &'a mut R)> where R: 'a;
+    type Adjacency<R> = GraphMap<EntityId, R, Self::EdgeType> where R: Relation;
+
+    fn is_exclusive_incoming() -> bool {
+        false
+    }
+
+    fn is_exclusive_outgoing() -> bool {
+        true
+    }
+
+    fn get_incoming<R, A>(graph: &A, entity: EntityId) -> Self::GetIncoming<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        edges(graph, entity, petgraph::Direction::Incoming)
+    }
+
+    fn get_outgoing<R, A>(graph: &A, entity: EntityId) -> Self::GetOutgoing<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
         graph
-            .edges_directed(entity, petgraph::Incoming)
-            .map(|(e, _, r)| (e, r))
+            .edges_directed(entity, petgraph::Direction::Outgoing)
             .next()
     }
 
-    fn get_outgoing<R>(
-        graph: &GraphMap<EntityId, R, Self::EdgeType>,
-        entity: EntityId,
-    ) -> Self::GetOutgoing<'_, R> {
-        Box::new(
-            graph
-                .edges_directed(entity, petgraph::Direction::Outgoing)
-                .map(|(_, e, r)| (e, r)),
-        )
+    fn get_incoming_mut<R, A>(graph: &mut A, entity: EntityId) -> Self::GetIncomingMut<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        edges_mut(graph, entity, petgraph::Direction::Incoming)
+    }
+
+    fn get_outgoing_mut<R, A>(graph: &mut A, entity: EntityId) -> Self::GetOutgoingMut<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        edge_mut(graph, entity, petgraph::Direction::Outgoing)
     }
 }
 
-impl RelationMode for DirectedExclusiveOutgoing {
+impl RelationMode for Tree {
     type EdgeType = petgraph::Directed;
     type GetIncoming<'a, R> = Box<dyn Iterator<Item = (EntityId, &'a R)> + 'a> where R: 'a;
     type GetOutgoing<'a, R> = Option<(EntityId, &'a R)> where R: 'a;
+    type GetIncomingMut<'a, R> = Box<dyn Iterator<Item = (EntityId, &'a mut R)> + 'a> where R: 'a;
+    type GetOutgoingMut<'a, R> = Option<(EntityId, &'a mut R)> where R: 'a;
+    type Adjacency<R> = GraphMap<EntityId, R, Self::EdgeType> where R: Relation;
 
     fn is_exclusive_incoming() -> bool {
         false
@@ -167,32 +383,56 @@ impl RelationMode for DirectedExclusiveOutgoing {
         true
     }
 
-    fn get_incoming<R>(
-        graph: &GraphMap<EntityId, R, Self::EdgeType>,
-        entity: EntityId,
-    ) -> Self::GetIncoming<'_, R> {
-        Box::new(
-            graph
-                .edges_directed(entity, petgraph::Direction::Incoming)
-                .map(|(e, _, r)| (e, r)),
-        )
+    fn is_acyclic() -> bool {
+        true
+    }
+
+    fn is_child_to_parent() -> bool {
+        true
     }
 
-    fn get_outgoing<R>(
-        graph: &GraphMap<EntityId, R, Self::EdgeType>,
-        entity: EntityId,
-    ) -> Self::GetOutgoing<'_, R> {
+    fn get_incoming<R, A>(graph: &A, entity: EntityId) -> Self::GetIncoming<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        edges(graph, entity, petgraph::Direction::Incoming)
+    }
+
+    fn get_outgoing<R, A>(graph: &A, entity: EntityId) -> Self::GetOutgoing<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
         graph
             .edges_directed(entity, petgraph::Direction::Outgoing)
-            .map(|(_, e, r)| (e, r))
             .next()
     }
+
+    fn get_incoming_mut<R, A>(graph: &mut A, entity: EntityId) -> Self::GetIncomingMut<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        edges_mut(graph, entity, petgraph::Direction::Incoming)
+    }
+
+    fn get_outgoing_mut<R, A>(graph: &mut A, entity: EntityId) -> Self::GetOutgoingMut<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        edge_mut(graph, entity, petgraph::Direction::Outgoing)
+    }
 }
 
 impl RelationMode for Undirected {
     type EdgeType = petgraph::Undirected;
     type GetIncoming<'a, R> = Box<dyn Iterator<Item = (EntityId, &'a R)> + 'a> where R: 'a;
     type GetOutgoing<'a, R> = Box<dyn Iterator<Item = (EntityId, &'a R)> + 'a> where R: 'a;
+    type GetIncomingMut<'a, R> = Box<dyn Iterator<Item = (EntityId, &'a mut R)> + 'a> where R: 'a;
+    type GetOutgoingMut<'a, R> = Box<dyn Iterator<Item = (EntityId, &'a mut R)> + 'a> where R: 'a;
+    type Adjacency<R> = GraphMap<EntityId, R, Self::EdgeType> where R: Relation;
 
     fn is_exclusive_incoming() -> bool {
         false
@@ -202,26 +442,36 @@ impl RelationMode for Undirected {
         false
     }
 
-    fn get_incoming<R>(
-        graph: &GraphMap<EntityId, R, Self::EdgeType>,
-        entity: EntityId,
-    ) -> Self::GetIncoming<'_, R> {
-        Box::new(
-            graph
-                .edges_directed(entity, petgraph::Direction::Incoming)
-                .map(|(e, _, r)| (e, r)),
-        )
+    fn get_incoming<R, A>(graph: &A, entity: EntityId) -> Self::GetIncoming<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        edges(graph, entity, petgraph::Direction::Incoming)
+    }
+
+    fn get_outgoing<R, A>(graph: &A, entity: EntityId) -> Self::GetOutgoing<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        edges(graph, entity, petgraph::Direction::Outgoing)
+    }
+
+    fn get_incoming_mut<R, A>(graph: &mut A, entity: EntityId) -> Self::GetIncomingMut<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        edges_mut(graph, entity, petgraph::Direction::Incoming)
     }
 
-    fn get_outgoing<R>(
-        graph: &GraphMap<EntityId, R, Self::EdgeType>,
-        entity: EntityId,
-    ) -> Self::GetOutgoing<'_, R> {
-        Box::new(
-            graph
-                .edges_directed(entity, petgraph::Direction::Outgoing)
-                .map(|(_, e, r)| (e, r)),
-        )
+    fn get_outgoing_mut<R, A>(graph: &mut A, entity: EntityId) -> Self::GetOutgoingMut<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        edges_mut(graph, entity, petgraph::Direction::Outgoing)
     }
 }
 
@@ -229,6 +479,9 @@ impl RelationMode for UndirectedExclusive {
     type EdgeType = petgraph::Undirected;
     type GetIncoming<'a, R> = Option<(EntityId, &'a R)> where R: 'a;
     type GetOutgoing<'a, R> = Option<(EntityId, &'a R)> where R: 'a;
+    type GetIncomingMut<'a, R> = Option<(EntityId, &'a mut R)> where R: 'a;
+    type GetOutgoingMut<'a, R> = Option<(EntityId, &'a mut R)> where R: 'a;
+    type Adjacency<R> = GraphMap<EntityId, R, Self::EdgeType> where R: Relation;
 
     fn is_exclusive_incoming() -> bool {
         true
@@ -238,23 +491,99 @@ impl RelationMode for UndirectedExclusive {
         true
     }
 
-    fn get_incoming<R>(
-        graph: &GraphMap<EntityId, R, Self::EdgeType>,
-        entity: EntityId,
-    ) -> Self::GetIncoming<'_, R> {
+    fn get_incoming<R, A>(graph: &A, entity: EntityId) -> Self::GetIncoming<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
         graph
             .edges_directed(entity, petgraph::Direction::Incoming)
-            .map(|(e, _, r)| (e, r))
             .next()
     }
 
-    fn get_outgoing<R>(
-        graph: &GraphMap<EntityId, R, Self::EdgeType>,
-        entity: EntityId,
-    ) -> Self::GetIncoming<'_, R> {
+    fn get_outgoing<R, A>(graph: &A, entity: EntityId) -> Self::GetIncoming<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
         graph
             .edges_directed(entity, petgraph::Direction::Outgoing)
-            .map(|(_, e, r)| (e, r))
             .next()
     }
+
+    fn get_incoming_mut<R, A>(graph: &mut A, entity: EntityId) -> Self::GetIncomingMut<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        edge_mut(graph, entity, petgraph::Direction::Incoming)
+    }
+
+    fn get_outgoing_mut<R, A>(graph: &mut A, entity: EntityId) -> Self::GetOutgoingMut<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        edge_mut(graph, entity, petgraph::Direction::Outgoing)
+    }
+}
+
+impl<M> RelationMode for SparseSet<M>
+where
+    M: RelationMode,
+{
+    type EdgeType = M::EdgeType;
+    type GetIncoming<'a, R> = M::GetIncoming<'a, R> where R: 'a;
+    type GetOutgoing<'a, R> = M::GetOutgoing<'a, R> where R: 'a;
+    type GetIncomingMut<'a, R> = M::GetIncomingMut<'a, R> where R: 'a;
+    type GetOutgoingMut<'a, R> = M::GetOutgoingMut<'a, R> where R: 'a;
+    type Adjacency<R> = SparseAdjacency<R, M::EdgeType> where R: Relation;
+
+    fn is_exclusive_incoming() -> bool {
+        M::is_exclusive_incoming()
+    }
+
+    fn is_exclusive_outgoing() -> bool {
+        M::is_exclusive_outgoing()
+    }
+
+    fn is_acyclic() -> bool {
+        M::is_acyclic()
+    }
+
+    fn is_child_to_parent() -> bool {
+        M::is_child_to_parent()
+    }
+
+    fn get_incoming<R, A>(graph: &A, entity: EntityId) -> Self::GetIncoming<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        M::get_incoming(graph, entity)
+    }
+
+    fn get_outgoing<R, A>(graph: &A, entity: EntityId) -> Self::GetOutgoing<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        M::get_outgoing(graph, entity)
+    }
+
+    fn get_incoming_mut<R, A>(graph: &mut A, entity: EntityId) -> Self::GetIncomingMut<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        M::get_incoming_mut(graph, entity)
+    }
+
+    fn get_outgoing_mut<R, A>(graph: &mut A, entity: EntityId) -> Self::GetOutgoingMut<'_, R>
+    where
+        R: Relation,
+        A: Adjacency<R>,
+    {
+        M::get_outgoing_mut(graph, entity)
+    }
 }
@@ -1,17 +1,101 @@
-use indexmap::IndexMap;
-use petgraph::prelude::GraphMap;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+};
+
+use indexmap::{IndexMap, IndexSet};
+use petgraph::{
+    algo::{has_path_connecting, kosaraju_scc},
+    visit::{Dfs, Reversed},
+    EdgeType,
+};
 use shipyard::*;
 
-use crate::{relation_mode::RelationMode, Relation};
+use crate::{
+    adjacency::{Adjacency, AdjacencyOf, Walk},
+    iter::LineageIter,
+    relation_mode::RelationMode,
+    GetRelation, InsertError, Relation, Retention, SelfLoops,
+};
+
+type InsertHook<R> = Arc<dyn Fn(EntityId, EntityId, &R) -> bool + Send + Sync>;
+type RelationHook<R> = Arc<dyn Fn(EntityId, EntityId, &R) + Send + Sync>;
+
+/// Callbacks invoked synchronously when relations are inserted, replaced or deleted.
+struct RelationHooks<R> {
+    on_insert: Option<InsertHook<R>>,
+    on_delete: Option<RelationHook<R>>,
+    on_replace: Option<RelationHook<R>>,
+}
+
+impl<R> Clone for RelationHooks<R> {
+    fn clone(&self) -> Self {
+        Self {
+            on_insert: self.on_insert.clone(),
+            on_delete: self.on_delete.clone(),
+            on_replace: self.on_replace.clone(),
+        }
+    }
+}
+
+impl<R> Default for RelationHooks<R> {
+    fn default() -> Self {
+        Self {
+            on_insert: None,
+            on_delete: None,
+            on_replace: None,
+        }
+    }
+}
+
+impl<R> StagedRelation<R> {
+    /// The relation, once taken out of the graph with `RelationStorage::unstage`.
+    pub(crate) fn into_relation(self) -> Option<R> {
+        self.relation
+    }
+}
+
+/// Entities without relations on one side, kept up to date as relations change.
+#[derive(Clone, Default)]
+pub(crate) struct Extremities {
+    pub(crate) roots: IndexSet<EntityId>,
+    pub(crate) leaves: IndexSet<EntityId>,
+    pub(crate) isolated: IndexSet<EntityId>,
+}
+
+/// Relation of a bundle inserted in the graph only, see `RelationStorage::stage`.
+pub struct StagedRelation<R> {
+    a: EntityId,
+    b: EntityId,
+    /// `None` while the relation is in the graph.
+    relation: Option<R>,
+    /// `false` for a self-loop dropped because of `Relation::SELF_LOOPS`.
+    accepted: bool,
+    replaced: Option<R>,
+    displaced: Vec<(EntityId, EntityId, R)>,
+    added: Vec<EntityId>,
+}
 
 pub struct RelationStorage<R>
 where
     R: Relation,
 {
-    pub(crate) graph: GraphMap<EntityId, R, <R::Mode as RelationMode>::EdgeType>,
+    pub(crate) graph: AdjacencyOf<R>,
     pub(crate) last_insert: TrackingTimestamp,
-    pub(crate) insertion_data: IndexMap<(EntityId, EntityId), TrackingTimestamp>,
-    pub(crate) deletion_data: IndexMap<(EntityId, EntityId), (TrackingTimestamp, R)>,
+    /// Number of tracked events, each event keeps its position in this count for `TrackingCursor`.
+    pub(crate) sequence: u64,
+    /// No tracking data is older than this, `None` when there was none since the last retention.
+    pub(crate) oldest: Option<TrackingTimestamp>,
+    pub(crate) insertion_data: IndexMap<(EntityId, EntityId), (TrackingTimestamp, u64)>,
+    pub(crate) deletion_data: IndexMap<(EntityId, EntityId), (TrackingTimestamp, u64, R)>,
+    /// Maps the new pair to the timestamp, the sequence, the entity that kept the relation
+    /// and its former partner.
+    pub(crate) retargeting_data:
+        IndexMap<(EntityId, EntityId), (TrackingTimestamp, u64, EntityId, EntityId)>,
+    /// Entities whose components synced with the relations may be outdated.
+    pub(crate) outdated: Vec<EntityId>,
+    pub(crate) extremities: Extremities,
+    hooks: RelationHooks<R>,
 }
 
 impl<R> RelationStorage<R>
@@ -20,14 +104,74 @@ where
 {
     pub fn new(current: TrackingTimestamp) -> Self {
         Self {
-            graph: GraphMap::default(),
+            graph: AdjacencyOf::<R>::default(),
             last_insert: current,
+            sequence: 0,
+            oldest: None,
             insertion_data: IndexMap::new(),
             deletion_data: IndexMap::new(),
+            retargeting_data: IndexMap::new(),
+            outdated: Vec::new(),
+            extremities: Extremities::default(),
+            hooks: RelationHooks::default(),
+        }
+    }
+
+    /// Sets a callback invoked before a new relation is inserted.
+    ///
+    /// Returning `false` rejects the relation with `InsertError::Rejected`.
+    pub fn on_insert<F>(&mut self, f: F)
+    where
+        F: Fn(EntityId, EntityId, &R) -> bool + Send + Sync + 'static,
+    {
+        self.hooks.on_insert = Some(Arc::new(f));
+    }
+
+    /// Sets a callback invoked after a relation is deleted, with the deleted data.
+    pub fn on_delete<F>(&mut self, f: F)
+    where
+        F: Fn(EntityId, EntityId, &R) + Send + Sync + 'static,
+    {
+        self.hooks.on_delete = Some(Arc::new(f));
+    }
+
+    /// Sets a callback invoked after the data of an existing relation is replaced, with the replaced data.
+    pub fn on_replace<F>(&mut self, f: F)
+    where
+        F: Fn(EntityId, EntityId, &R) + Send + Sync + 'static,
+    {
+        self.hooks.on_replace = Some(Arc::new(f));
+    }
+}
+
+/// Clones the relations, their tracking data and the hooks, which are shared with the clone.
+impl<R> Clone for RelationStorage<R>
+where
+    R: Relation + Clone,
+    AdjacencyOf<R>: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            graph: self.graph.clone(),
+            last_insert: self.last_insert,
+            sequence: self.sequence,
+            oldest: self.oldest,
+            insertion_data: self.insertion_data.clone(),
+            deletion_data: self.deletion_data.clone(),
+            retargeting_data: self.retargeting_data.clone(),
+            outdated: self.outdated.clone(),
+            extremities: self.extremities.clone(),
+            hooks: self.hooks.clone(),
         }
     }
 }
 
+// `try_clone` and `move_component_from` keep their defaults, relations aren't cloned or moved by shipyard:
+// - cloning needs `R: Clone`, which can't be required of every relation from this impl,
+//   `RelationViewMut::clone_relations_from` does it for the relations that are `Clone`.
+//   A cloned `World`, for lookahead for example, starts without relations until it is called.
+// - entities are moved one at a time, the other side of a relation isn't moved yet and
+//   its new id is unknown, `RelationViewMut::move_relations_to` moves them with the whole mapping.
 impl<R> Storage for RelationStorage<R>
 where
     R: Relation,
@@ -43,15 +187,34 @@ where
 
     fn delete(&mut self, entity: EntityId, current: TrackingTimestamp) {
         self.delete_node_tracked(entity, current);
+
+        // the components of a deleted entity are deleted with it
+        if !self.outdated.is_empty() {
+            self.outdated.retain(|&e| e != entity);
+        }
+    }
+
+    fn clear(&mut self, current: TrackingTimestamp) {
+        self.delete_all_tracked(current);
+        self.outdated.clear();
+    }
+
+    fn clear_all_inserted(&mut self, current: TrackingTimestamp) {
+        self.clear_inserted(current);
+        self.apply_retention(current);
+    }
+
+    fn clear_all_inserted_and_modified(&mut self, current: TrackingTimestamp) {
+        self.clear_inserted(current);
+        self.apply_retention(current);
     }
 
     fn clear_all_removed_and_deleted(&mut self) {
-        self.deletion_data.clear();
+        self.clear_deleted();
     }
 
     fn clear_all_removed_and_deleted_older_than_timestamp(&mut self, timestamp: TrackingTimestamp) {
-        self.deletion_data
-            .retain(|_, (t, _)| timestamp.is_older_than(*t));
+        self.clear_deleted_older_than_timestamp(timestamp);
     }
 }
 
@@ -59,10 +222,230 @@ impl<R> RelationStorage<R>
 where
     R: Relation,
 {
-    pub fn graph(&self) -> &GraphMap<EntityId, R, <R::Mode as RelationMode>::EdgeType> {
+    pub fn graph(&self) -> &<R::Mode as RelationMode>::Adjacency<R> {
         &self.graph
     }
 
+    /// Returns `true` if cycles are prevented, by `Relation::ACYCLIC` or the mode itself.
+    pub(crate) fn is_acyclic() -> bool {
+        R::ACYCLIC || R::Mode::is_acyclic()
+    }
+
+    /// Direction of the relations going from a parent to its children.
+    ///
+    /// `Incoming` for modes storing relations from the child to the parent, like `Tree`.
+    pub(crate) fn down() -> petgraph::Direction {
+        if R::Mode::is_child_to_parent() {
+            petgraph::Direction::Incoming
+        } else {
+            petgraph::Direction::Outgoing
+        }
+    }
+
+    /// Direction of the relations going from a child to its parents, see `down`.
+    pub(crate) fn up() -> petgraph::Direction {
+        Self::down().opposite()
+    }
+
+    /// Returns `true` if `to` can be reached from `from` by following relations.
+    pub(crate) fn has_path(&self, from: EntityId, to: EntityId) -> bool {
+        has_path_connecting(Walk::new(&self.graph, false), from, to, None)
+    }
+
+    /// Returns `true` if `a` and `b` are connected, following relations in both directions.
+    pub(crate) fn is_connected(&self, a: EntityId, b: EntityId) -> bool {
+        let mut visited = HashSet::from([a]);
+        let mut stack = vec![a];
+
+        while let Some(node) = stack.pop() {
+            if node == b {
+                return true;
+            }
+
+            let neighbors = self
+                .graph
+                .neighbors_directed(node, petgraph::Direction::Outgoing)
+                .chain(
+                    self.graph
+                        .neighbors_directed(node, petgraph::Direction::Incoming),
+                );
+
+            for neighbor in neighbors {
+                if visited.insert(neighbor) {
+                    stack.push(neighbor);
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Clears insertion and retargeting tracking data.
+    ///
+    /// Views borrowed outside of a system only see what is inserted after `current`.
+    pub fn clear_inserted(&mut self, current: TrackingTimestamp) {
+        self.last_insert = current;
+        self.insertion_data.clear();
+        self.retargeting_data.clear();
+    }
+
+    /// Clears insertion and retargeting tracking data older than `timestamp`.
+    pub fn clear_inserted_older_than_timestamp(&mut self, timestamp: TrackingTimestamp) {
+        self.insertion_data
+            .retain(|_, (t, _)| timestamp.is_older_than(*t));
+        self.retargeting_data
+            .retain(|_, (t, _, _, _)| timestamp.is_older_than(*t));
+    }
+
+    /// Clears deletion tracking data, dropping the data of deleted relations.
+    pub fn clear_deleted(&mut self) {
+        self.deletion_data.clear();
+    }
+
+    /// Clears deletion tracking data older than `timestamp`, dropping the data of these relations.
+    pub fn clear_deleted_older_than_timestamp(&mut self, timestamp: TrackingTimestamp) {
+        self.deletion_data
+            .retain(|_, (t, _, _)| timestamp.is_older_than(*t));
+    }
+
+    /// Drops the tracking data that `Relation::RETENTION` doesn't keep anymore.
+    pub(crate) fn apply_retention(&mut self, current: TrackingTimestamp) {
+        if let Retention::MaxAge(max_age) = R::RETENTION {
+            let timestamp = TrackingTimestamp::new(current.get().wrapping_sub(max_age));
+
+            // nothing expires before the oldest tracking data, which spares a pass over all of it
+            match self.oldest {
+                Some(oldest) if !timestamp.is_older_than(oldest) => {}
+                _ => return,
+            }
+
+            self.clear_inserted_older_than_timestamp(timestamp);
+            self.clear_deleted_older_than_timestamp(timestamp);
+
+            self.oldest = self
+                .insertion_data
+                .values()
+                .map(|(t, _)| *t)
+                .chain(self.retargeting_data.values().map(|(t, _, _, _)| *t))
+                .chain(self.deletion_data.values().map(|(t, _, _)| *t))
+                .reduce(|oldest, t| if t.is_older_than(oldest) { t } else { oldest });
+        }
+    }
+
+    /// Key of the relation between `a` and `b` in the tracking data.
+    ///
+    /// Undirected relations are always ordered with the smaller `EntityId` first,
+    /// no matter in which order their entities are passed.
+    pub(crate) fn key(a: EntityId, b: EntityId) -> (EntityId, EntityId) {
+        if !<R::Mode as RelationMode>::EdgeType::is_directed() && b < a {
+            (b, a)
+        } else {
+            (a, b)
+        }
+    }
+
+    /// Neighbors of `entity`, sorted by `EntityId` for deterministic relations.
+    pub(crate) fn neighbors(
+        &self,
+        entity: EntityId,
+        direction: petgraph::Direction,
+    ) -> Vec<EntityId> {
+        let mut neighbors = self
+            .graph
+            .neighbors_directed(entity, direction)
+            .collect::<Vec<_>>();
+
+        if R::DETERMINISTIC {
+            neighbors.sort_unstable();
+        }
+
+        neighbors
+    }
+
+    pub(crate) fn has_neighbor(&self, entity: EntityId, direction: petgraph::Direction) -> bool {
+        self.graph
+            .neighbors_directed(entity, direction)
+            .next()
+            .is_some()
+    }
+
+    /// Marks the components synced with the relations of `entity` as outdated.
+    pub(crate) fn touch(&mut self, entity: EntityId) {
+        if R::MARKERS || R::MIRROR {
+            self.outdated.push(entity);
+        }
+    }
+
+    /// Updates `extremities` after the relations of `entity` changed, see `GetRelation::roots`.
+    pub(crate) fn classify(&mut self, entity: EntityId) {
+        let exists = self.graph.contains_node(entity);
+        let outgoing = self.has_neighbor(entity, petgraph::Direction::Outgoing);
+        let incoming = self.has_neighbor(entity, petgraph::Direction::Incoming);
+
+        let (root, leaf) = if !<R::Mode as RelationMode>::EdgeType::is_directed() {
+            let mut neighbors = self
+                .graph
+                .neighbors_directed(entity, petgraph::Direction::Outgoing);
+
+            (
+                false,
+                neighbors.next().is_some() && neighbors.next().is_none(),
+            )
+        } else if R::Mode::is_child_to_parent() {
+            (incoming && !outgoing, outgoing && !incoming)
+        } else {
+            (outgoing && !incoming, incoming && !outgoing)
+        };
+
+        let update = |set: &mut IndexSet<EntityId>, included: bool| {
+            if included {
+                set.insert(entity);
+            } else {
+                set.swap_remove(&entity);
+            }
+        };
+
+        update(&mut self.extremities.roots, root);
+        update(&mut self.extremities.leaves, leaf);
+        update(
+            &mut self.extremities.isolated,
+            exists && !outgoing && !incoming,
+        );
+    }
+
+    /// Adds or updates tracking data.
+    ///
+    /// For deterministic relations, the entry moves to the end to keep the tracking data
+    /// in the order of the events.
+    fn track<V>(map: &mut IndexMap<(EntityId, EntityId), V>, key: (EntityId, EntityId), value: V) {
+        if R::DETERMINISTIC {
+            map.shift_remove(&key);
+        }
+
+        map.insert(key, value);
+    }
+
+    /// Sequence of a new event tracked at `current`.
+    fn next_sequence(&mut self, current: TrackingTimestamp) -> u64 {
+        self.oldest.get_or_insert(current);
+        self.sequence += 1;
+        self.sequence
+    }
+
+    /// Removes tracking data.
+    ///
+    /// For deterministic relations, the order of the remaining entries is preserved.
+    fn untrack<V>(
+        map: &mut IndexMap<(EntityId, EntityId), V>,
+        key: &(EntityId, EntityId),
+    ) -> Option<V> {
+        if R::DETERMINISTIC {
+            map.shift_remove(key)
+        } else {
+            map.swap_remove(key)
+        }
+    }
+
     pub(crate) fn delete_edge_tracked(
         &mut self,
         a: EntityId,
@@ -70,38 +453,715 @@ where
         current: TrackingTimestamp,
     ) -> bool {
         if let Some(r) = self.graph.remove_edge(a, b) {
-            self.insertion_data.remove(&(a, b));
-            self.deletion_data.insert((a, b), (current, r));
+            if let Some(on_delete) = &self.hooks.on_delete {
+                on_delete(a, b, &r);
+            }
+
+            self.touch(a);
+            self.touch(b);
+            self.classify(a);
+            self.classify(b);
+
+            let key = Self::key(a, b);
+            if R::TRACKING.tracks_insertion() {
+                Self::untrack(&mut self.insertion_data, &key);
+                Self::untrack(&mut self.retargeting_data, &key);
+            }
+            if R::TRACKING.tracks_deletion() {
+                let sequence = self.next_sequence(current);
+                Self::track(&mut self.deletion_data, key, (current, sequence, r));
+            }
             true
         } else {
             false
         }
     }
 
+    /// Removes the relation between `a` and `b` without tracking its deletion, forgetting its insertion.
+    pub(crate) fn take_edge(&mut self, a: EntityId, b: EntityId) -> Option<R> {
+        let relation = self.graph.remove_edge(a, b)?;
+        self.forget_edge(a, b);
+
+        Some(relation)
+    }
+
+    /// Updates everything but the graph after the relation between `a` and `b` was removed
+    /// from it, forgetting its insertion.
+    pub(crate) fn forget_edge(&mut self, a: EntityId, b: EntityId) {
+        self.touch(a);
+        self.touch(b);
+        self.classify(a);
+        self.classify(b);
+
+        let key = Self::key(a, b);
+        Self::untrack(&mut self.insertion_data, &key);
+        Self::untrack(&mut self.retargeting_data, &key);
+    }
+
     pub(crate) fn delete_node_tracked(
         &mut self,
         entity: EntityId,
         current: TrackingTimestamp,
     ) -> bool {
-        for e in self
-            .graph
-            .neighbors_directed(entity, petgraph::Direction::Incoming)
-            .collect::<Vec<_>>()
-        {
+        for e in self.neighbors(entity, petgraph::Direction::Incoming) {
             self.delete_edge_tracked(e, entity, current);
         }
 
-        for e in self
+        for e in self.neighbors(entity, petgraph::Direction::Outgoing) {
+            self.delete_edge_tracked(entity, e, current);
+        }
+
+        let removed = self.graph.remove_node(entity);
+        self.classify(entity);
+
+        removed
+    }
+
+    /// Deletes all relations and entities, tracking each deleted relation.
+    pub(crate) fn delete_all_tracked(&mut self, current: TrackingTimestamp) {
+        let mut nodes = self.graph.nodes().collect::<Vec<_>>();
+        if R::DETERMINISTIC {
+            nodes.sort_unstable();
+        }
+
+        for node in nodes {
+            self.delete_node_tracked(node, current);
+        }
+    }
+
+    /// All relations, undirected relations only once with the smaller `EntityId` first.
+    pub(crate) fn pairs(&self) -> Vec<(EntityId, EntityId)> {
+        let directed = <R::Mode as RelationMode>::EdgeType::is_directed();
+
+        let mut nodes = self.graph.nodes().collect::<Vec<_>>();
+        if R::DETERMINISTIC {
+            nodes.sort_unstable();
+        }
+
+        nodes
+            .into_iter()
+            .flat_map(|a| {
+                self.neighbors(a, petgraph::Direction::Outgoing)
+                    .into_iter()
+                    .filter(move |&b| directed || a <= b)
+                    .map(move |b| (a, b))
+            })
+            .collect()
+    }
+
+    /// Returns the cycle inserting a relation between `a` and `b` would create,
+    /// as the path of entities from `b` back to `a`.
+    ///
+    /// Relations removed because of the exclusiveness of the mode are taken into account.
+    /// Self-loops are governed by `Relation::SELF_LOOPS` and never count as cycles.
+    pub(crate) fn cycle_path(&self, a: EntityId, b: EntityId) -> Option<Vec<EntityId>> {
+        if a == b || self.graph.contains_edge(a, b) {
+            return None;
+        }
+
+        if !<R::Mode as RelationMode>::EdgeType::is_directed() && R::Mode::is_exclusive_outgoing() {
+            // both entities lose their only other relation
+            None
+        } else {
+            self.path(b, a)
+        }
+    }
+
+    /// Shortest path of entities from `from` to `to` following relations, both included.
+    ///
+    /// Acyclic relations exclusive on the outgoing side only walk the chain starting at `from`.
+    pub(crate) fn path(&self, from: EntityId, to: EntityId) -> Option<Vec<EntityId>> {
+        if <R::Mode as RelationMode>::EdgeType::is_directed()
+            && R::Mode::is_exclusive_outgoing()
+            && Self::is_acyclic()
+        {
+            let mut path = vec![from];
+            let mut node = from;
+
+            while node != to {
+                node = self
+                    .graph
+                    .neighbors_directed(node, petgraph::Direction::Outgoing)
+                    .find(|next| *next != node)?;
+                path.push(node);
+            }
+
+            return Some(path);
+        }
+
+        self.path_through(from, to, |_, _| true)
+    }
+
+    /// Same as `path`, only following the relations accepted by `accept`,
+    /// called with the entity the path is at and the next one.
+    pub(crate) fn path_through<F>(
+        &self,
+        from: EntityId,
+        to: EntityId,
+        accept: F,
+    ) -> Option<Vec<EntityId>>
+    where
+        F: Fn(EntityId, EntityId) -> bool,
+    {
+        let mut previous = HashMap::from([(from, from)]);
+        let mut queue = VecDeque::from([from]);
+
+        while let Some(node) = queue.pop_front() {
+            if node == to {
+                let mut path = vec![to];
+                let mut node = to;
+                while node != from {
+                    node = previous[&node];
+                    path.push(node);
+                }
+                path.reverse();
+
+                return Some(path);
+            }
+
+            for neighbor in self.neighbors(node, petgraph::Direction::Outgoing) {
+                if accept(node, neighbor) {
+                    previous.entry(neighbor).or_insert_with(|| {
+                        queue.push_back(neighbor);
+                        node
+                    });
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Strongly connected components, see `GetRelation::strongly_connected_components`.
+    pub(crate) fn strongly_connected_components(&self) -> Vec<Vec<EntityId>> {
+        let mut components = kosaraju_scc(Walk::new(&self.graph, R::DETERMINISTIC));
+
+        if R::DETERMINISTIC {
+            for component in &mut components {
+                component.sort_unstable();
+            }
+        }
+
+        components
+    }
+
+    /// A cycle as the entities along it, see `GetRelation::find_cycle`.
+    pub(crate) fn find_cycle(&self) -> Option<Vec<EntityId>> {
+        if !<R::Mode as RelationMode>::EdgeType::is_directed() {
+            return None;
+        }
+
+        self.strongly_connected_components()
+            .into_iter()
+            .find_map(|component| {
+                let start = component[0];
+                let neighbors = self.neighbors(start, petgraph::Direction::Outgoing);
+
+                if neighbors.contains(&start) {
+                    return Some(vec![start]);
+                }
+
+                // any path between two entities of a component stays in the component
+                let next = neighbors
+                    .into_iter()
+                    .find(|neighbor| component.contains(neighbor))?;
+                let mut cycle = self.path(next, start)?;
+                cycle.pop();
+                cycle.insert(0, start);
+
+                Some(cycle)
+            })
+    }
+
+    /// All simple cycles going through `entity`, see `GetRelation::cycles_through`.
+    pub(crate) fn cycles_through(&self, entity: EntityId) -> Vec<Vec<EntityId>> {
+        let mut cycles = Vec::new();
+
+        if !<R::Mode as RelationMode>::EdgeType::is_directed() {
+            return cycles;
+        }
+
+        // only entities both reachable from `entity` and reaching it can be part of a cycle
+        let below = LineageIter::<R>::new(&self.graph, entity, petgraph::Direction::Outgoing)
+            .collect::<HashSet<_>>();
+        let component = LineageIter::<R>::new(&self.graph, entity, petgraph::Direction::Incoming)
+            .filter(|other| below.contains(other))
+            .collect::<HashSet<_>>();
+
+        self.search_cycles(entity, &component, &mut vec![entity], &mut cycles);
+
+        cycles
+    }
+
+    fn search_cycles(
+        &self,
+        start: EntityId,
+        component: &HashSet<EntityId>,
+        path: &mut Vec<EntityId>,
+        cycles: &mut Vec<Vec<EntityId>>,
+    ) {
+        let node = *path.last().unwrap();
+
+        for next in self.neighbors(node, petgraph::Direction::Outgoing) {
+            if next == start {
+                cycles.push(path.clone());
+            } else if component.contains(&next) && !path.contains(&next) {
+                path.push(next);
+                self.search_cycles(start, component, path, cycles);
+                path.pop();
+            }
+        }
+    }
+
+    /// Relations of `entity` in `direction`, sorted by `EntityId` for deterministic relations.
+    pub(crate) fn edges(
+        &self,
+        entity: EntityId,
+        direction: petgraph::Direction,
+    ) -> Vec<(EntityId, &R)> {
+        let mut edges = self
             .graph
-            .neighbors_directed(entity, petgraph::Direction::Outgoing)
-            .collect::<Vec<_>>()
+            .edges_directed(entity, direction)
+            .collect::<Vec<_>>();
+
+        if R::DETERMINISTIC {
+            edges.sort_unstable_by_key(|(other, _)| *other);
+        }
+
+        edges
+    }
+
+    /// Entities in topological order from parents to children, only the ones below `starts` if provided.
+    ///
+    /// Entities on cycles and the ones only reachable through them are left out,
+    /// self-loops are ignored.
+    pub(crate) fn topological_order(&self, starts: Option<&[EntityId]>) -> Vec<EntityId> {
+        let (down, up) = (Self::down(), Self::up());
+
+        let nodes = match starts {
+            Some(starts) => {
+                let walk = Walk::new(&self.graph, R::DETERMINISTIC);
+                let mut dfs = Dfs::empty(walk);
+                dfs.stack.extend(starts.iter().rev());
+
+                let mut nodes = Vec::new();
+                loop {
+                    let node = if R::Mode::is_child_to_parent() {
+                        dfs.next(Reversed(walk))
+                    } else {
+                        dfs.next(walk)
+                    };
+
+                    let Some(node) = node else {
+                        break;
+                    };
+                    nodes.push(node);
+                }
+
+                nodes
+            }
+            None => {
+                let mut nodes = self.graph.nodes().collect::<Vec<_>>();
+                if R::DETERMINISTIC {
+                    nodes.sort_unstable();
+                }
+
+                nodes
+            }
+        };
+
+        let included = nodes.iter().copied().collect::<HashSet<_>>();
+        let mut in_degree = nodes
+            .iter()
+            .map(|&node| {
+                let degree = self
+                    .graph
+                    .neighbors_directed(node, up)
+                    .filter(|other| *other != node && included.contains(other))
+                    .count();
+
+                (node, degree)
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut queue = nodes
+            .iter()
+            .copied()
+            .filter(|node| in_degree[node] == 0)
+            .collect::<VecDeque<_>>();
+        let mut order = Vec::with_capacity(nodes.len());
+
+        while let Some(node) = queue.pop_front() {
+            order.push(node);
+
+            for other in self.neighbors(node, down) {
+                if other == node {
+                    continue;
+                }
+
+                if let Some(degree) = in_degree.get_mut(&other) {
+                    *degree -= 1;
+                    if *degree == 0 {
+                        queue.push_back(other);
+                    }
+                }
+            }
+        }
+
+        order
+    }
+
+    /// Computes the values below `starts`, see `GetRelation::propagate_down`.
+    ///
+    /// Entities in `starts` keep their value even when reachable from another one.
+    pub(crate) fn propagate<T, F>(
+        &self,
+        starts: Vec<(EntityId, T)>,
+        mut f: F,
+    ) -> HashMap<EntityId, T>
+    where
+        F: FnMut(&T, EntityId, &R) -> T,
+    {
+        let roots = starts.iter().map(|(root, _)| *root).collect::<Vec<_>>();
+        let mut fixed = roots.iter().copied().collect::<HashSet<_>>();
+        let mut values = starts.into_iter().collect::<HashMap<_, _>>();
+
+        if <R::Mode as RelationMode>::EdgeType::is_directed() {
+            for node in self.topological_order(Some(&roots)) {
+                let Some(value) = values.get(&node) else {
+                    continue;
+                };
+
+                let children = self
+                    .edges(node, Self::down())
+                    .into_iter()
+                    .filter(|(child, _)| !fixed.contains(child))
+                    .map(|(child, relation)| (child, f(value, child, relation)))
+                    .collect::<Vec<_>>();
+
+                values.extend(children);
+            }
+        } else {
+            let mut queue = roots.into_iter().collect::<VecDeque<_>>();
+
+            while let Some(node) = queue.pop_front() {
+                let value = &values[&node];
+
+                let children = self
+                    .edges(node, petgraph::Direction::Outgoing)
+                    .into_iter()
+                    .filter(|(child, _)| fixed.insert(*child))
+                    .map(|(child, relation)| (child, f(value, child, relation)))
+                    .collect::<Vec<_>>();
+
+                queue.extend(children.iter().map(|(child, _)| *child));
+                values.extend(children);
+            }
+        }
+
+        values
+    }
+
+    /// Recomputes the values of `changed` and everything below them, see `GetRelation::propagate_down_inserted`.
+    pub(crate) fn repropagate<T, F>(
+        &self,
+        values: &mut HashMap<EntityId, T>,
+        changed: Vec<EntityId>,
+        f: F,
+    ) where
+        T: Clone,
+        F: FnMut(&T, EntityId, &R) -> T,
+    {
+        let mut dirty = changed
+            .into_iter()
+            .filter(|&entity| self.graph.contains_node(entity))
+            .collect::<IndexSet<_>>();
+
+        let mut index = 0;
+        while let Some(&entity) = dirty.get_index(index) {
+            for child in self.neighbors(entity, Self::down()) {
+                dirty.insert(child);
+            }
+            index += 1;
+        }
+
+        if R::DETERMINISTIC {
+            dirty.sort_unstable();
+        }
+
+        // the topmost dirty entities start from their clean parents, or their own value without any
+        let mut seen = HashSet::new();
+        let mut starts = Vec::new();
+        for &entity in &dirty {
+            let parents = self.neighbors(entity, Self::up());
+            let clean = parents
+                .iter()
+                .filter(|parent| !dirty.contains(*parent) && values.contains_key(*parent))
+                .collect::<Vec<_>>();
+
+            if !clean.is_empty() {
+                starts.extend(
+                    clean
+                        .into_iter()
+                        .filter(|&&parent| seen.insert(parent))
+                        .map(|parent| (*parent, values[parent].clone())),
+                );
+            } else if !parents.iter().any(|parent| dirty.contains(parent)) {
+                if let Some(value) = values.get(&entity) {
+                    starts.push((entity, value.clone()));
+                }
+            }
+        }
+
+        let updated = self.propagate(starts, f);
+        values.extend(updated);
+    }
+
+    /// Computes the values of all entities from the ones of their children, see `GetRelation::fold_up`.
+    pub(crate) fn fold_up<T, F>(&self, mut f: F) -> HashMap<EntityId, T>
+    where
+        F: FnMut(&mut dyn Iterator<Item = (&T, &R)>, EntityId) -> T,
+    {
+        let mut values = HashMap::new();
+
+        if !<R::Mode as RelationMode>::EdgeType::is_directed() {
+            return values;
+        }
+
+        for node in self.topological_order(None).into_iter().rev() {
+            let value = {
+                let mut children = self
+                    .edges(node, Self::down())
+                    .into_iter()
+                    .filter_map(|(child, relation)| Some((values.get(&child)?, relation)));
+
+                f(&mut children, node)
+            };
+
+            values.insert(node, value);
+        }
+
+        values
+    }
+
+    /// Runs the checks of `RelationViewMut::insert_checked`, `view` is passed to `Relation::validate`.
+    ///
+    /// `on_insert` isn't called, see `check_on_insert`.
+    /// Returns `Ok(false)` if the relation is dropped because of `Relation::SELF_LOOPS`.
+    pub(crate) fn check_insert<G>(
+        &self,
+        view: &G,
+        a: EntityId,
+        b: EntityId,
+        relation: &R,
+    ) -> Result<bool, InsertError>
+    where
+        G: GetRelation<R>,
+    {
+        if a == b {
+            match R::SELF_LOOPS {
+                _ if R::Mode::is_acyclic() => return Err(InsertError::SelfLoop),
+                SelfLoops::Allow => {}
+                SelfLoops::Reject => return Err(InsertError::SelfLoop),
+                SelfLoops::Ignore => return Ok(false),
+            }
+        }
+
+        self.check_constraints(view, a, b, relation, false)?;
+
+        if Self::is_acyclic() {
+            if let Some(path) = self.cycle_path(a, b) {
+                return Err(InsertError::CycleDetected { a, b, path });
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Checks the constraints declared by `R` for a relation between `a` and `b`, except acyclicity.
+    ///
+    /// `retarget` indicates that `a` keeps its number of relations.
+    pub(crate) fn check_constraints<G>(
+        &self,
+        view: &G,
+        a: EntityId,
+        b: EntityId,
+        relation: &R,
+        retarget: bool,
+    ) -> Result<(), InsertError>
+    where
+        G: GetRelation<R>,
+    {
+        let graph = &self.graph;
+        let directed = <R::Mode as RelationMode>::EdgeType::is_directed();
+
+        if R::SYMMETRIC_UNIQUE && directed && a != b && graph.contains_edge(b, a) {
+            return Err(InsertError::SymmetricDuplicate);
+        }
+
+        // replacing the data of a relation doesn't change any count
+        if !graph.contains_edge(a, b) {
+            let count = |e, direction| graph.neighbors_directed(e, direction).count();
+            let outgoing_full = |max| {
+                !retarget
+                    && !R::Mode::is_exclusive_outgoing()
+                    && count(a, petgraph::Direction::Outgoing) >= max
+            };
+            let incoming_full = |max| {
+                !R::Mode::is_exclusive_incoming() && count(b, petgraph::Direction::Incoming) >= max
+            };
+
+            if let Some(max) = R::MAX_OUTGOING {
+                if outgoing_full(max) || (!directed && incoming_full(max)) {
+                    return Err(InsertError::MaxOutgoingExceeded);
+                }
+            }
+            if let Some(max) = R::MAX_INCOMING {
+                if incoming_full(max) || (!directed && outgoing_full(max)) {
+                    return Err(InsertError::MaxIncomingExceeded);
+                }
+            }
+        }
+
+        R::validate(view, a, b, relation).map_err(InsertError::Invalid)
+    }
+
+    /// Asks `on_insert` whether a new relation between `a` and `b` is accepted.
+    ///
+    /// Replacing the data of an existing relation isn't an insertion and is always accepted.
+    /// Called once per insertion, right before the relation is inserted.
+    pub(crate) fn check_on_insert(
+        &self,
+        a: EntityId,
+        b: EntityId,
+        relation: &R,
+    ) -> Result<(), InsertError> {
+        if self.graph.contains_edge(a, b) {
+            return Ok(());
+        }
+
+        self.run_on_insert(a, b, relation)
+    }
+
+    /// Asks `on_insert` whether the relation between `a` and `b` is accepted, even if it already exists.
+    pub(crate) fn run_on_insert(
+        &self,
+        a: EntityId,
+        b: EntityId,
+        relation: &R,
+    ) -> Result<(), InsertError> {
+        match &self.hooks.on_insert {
+            Some(on_insert) if !on_insert(a, b, relation) => Err(InsertError::Rejected),
+            _ => Ok(()),
+        }
+    }
+
+    /// Inserts a relation accepted by `check_insert` in the graph only, so that the checks of the
+    /// next relations of a bundle take it into account.
+    ///
+    /// Nothing is tracked and no hook is called, `unstage` puts the graph back as it was.
+    pub(crate) fn stage(
+        &mut self,
+        a: EntityId,
+        b: EntityId,
+        relation: R,
+        accepted: bool,
+    ) -> StagedRelation<R> {
+        let mut staged = StagedRelation {
+            a,
+            b,
+            relation: Some(relation),
+            accepted,
+            replaced: None,
+            displaced: Vec::new(),
+            added: Vec::new(),
+        };
+
+        // the relations of an older generation are deleted by `insert_staged`, with tracking
+        if !accepted
+            || [a, b]
+                .into_iter()
+                .any(|e| self.graph.stale_node(e).is_some())
         {
-            self.delete_edge_tracked(entity, e, current);
+            return staged;
+        }
+
+        if R::Mode::is_exclusive_incoming() {
+            for e in self.neighbors(b, petgraph::Direction::Incoming) {
+                if e != a {
+                    let relation = self.graph.remove_edge(e, b).unwrap();
+                    staged.displaced.push((e, b, relation));
+                }
+            }
         }
+        if R::Mode::is_exclusive_outgoing() {
+            for e in self.neighbors(a, petgraph::Direction::Outgoing) {
+                if e != b {
+                    if let Some(relation) = self.graph.remove_edge(a, e) {
+                        staged.displaced.push((a, e, relation));
+                    }
+                }
+            }
+        }
+
+        staged.added = [a, b]
+            .into_iter()
+            .filter(|&e| !self.graph.contains_node(e))
+            .collect();
+        staged.added.dedup();
+
+        staged.replaced = self.graph.add_edge(a, b, staged.relation.take().unwrap());
 
-        self.graph.remove_node(entity)
+        staged
     }
 
+    /// Takes a relation staged with `stage` out of the graph and restores what it replaced.
+    pub(crate) fn unstage(&mut self, staged: &mut StagedRelation<R>) {
+        if staged.relation.is_some() {
+            return;
+        }
+
+        let (a, b) = (staged.a, staged.b);
+        staged.relation = self.graph.remove_edge(a, b);
+
+        if let Some(replaced) = staged.replaced.take() {
+            self.graph.add_edge(a, b, replaced);
+        }
+        for (a, b, relation) in staged.displaced.drain(..).rev() {
+            self.graph.add_edge(a, b, relation);
+        }
+        for entity in staged.added.drain(..) {
+            self.graph.remove_node(entity);
+        }
+    }
+
+    /// Asks `on_insert` about a relation taken out of the graph with `unstage`.
+    pub(crate) fn check_on_insert_staged(
+        &self,
+        staged: &StagedRelation<R>,
+    ) -> Result<(), InsertError> {
+        match &staged.relation {
+            Some(relation) if staged.accepted => self.check_on_insert(staged.a, staged.b, relation),
+            _ => Ok(()),
+        }
+    }
+
+    /// Inserts a relation taken out of the graph with `unstage`, see `insert_tracked`.
+    pub(crate) fn insert_staged(&mut self, staged: StagedRelation<R>, current: TrackingTimestamp) {
+        if let (Some(relation), true) = (staged.relation, staged.accepted) {
+            self.insert_tracked(staged.a, staged.b, relation, current);
+        }
+    }
+
+    /// Deletes the relations of an older generation of `entity` the graph can't tell apart from it,
+    /// tracking each deletion, before `entity` gets relations.
+    fn delete_stale_node(&mut self, entity: EntityId, current: TrackingTimestamp) {
+        if let Some(old) = self.graph.stale_node(entity) {
+            self.delete_node_tracked(old, current);
+        }
+    }
+
+    /// Inserts a relation between `a` and `b` without checking it, see `check_insert`.
     pub(crate) fn insert_tracked(
         &mut self,
         a: EntityId,
@@ -109,8 +1169,110 @@ where
         relation: R,
         current: TrackingTimestamp,
     ) {
-        if self.graph.add_edge(a, b, relation).is_none() {
-            self.insertion_data.insert((a, b), current);
+        self.delete_stale_node(a, current);
+        self.delete_stale_node(b, current);
+
+        if R::Mode::is_exclusive_incoming() {
+            for e in self.neighbors(b, petgraph::Direction::Incoming) {
+                if e != a {
+                    self.delete_edge_tracked(e, b, current);
+                }
+            }
+        }
+        if R::Mode::is_exclusive_outgoing() {
+            for e in self.neighbors(a, petgraph::Direction::Outgoing) {
+                if e != b {
+                    self.delete_edge_tracked(a, e, current);
+                }
+            }
+        }
+
+        match self.graph.add_edge(a, b, relation) {
+            Some(old) => {
+                if let Some(on_replace) = &self.hooks.on_replace {
+                    on_replace(a, b, &old);
+                }
+            }
+            None => {
+                self.touch(a);
+                self.touch(b);
+                self.classify(a);
+                self.classify(b);
+
+                if R::TRACKING.tracks_insertion() {
+                    let sequence = self.next_sequence(current);
+                    Self::track(
+                        &mut self.insertion_data,
+                        Self::key(a, b),
+                        (current, sequence),
+                    );
+                }
+            }
         }
     }
+
+    /// Moves the relation between `a` and `old_b` to `a` and `new_b`.
+    ///
+    /// The move is tracked as a single retargeting instead of a deletion and an insertion,
+    /// hooks see the relation deleted between `a` and `old_b` and inserted between `a` and `new_b`.
+    pub(crate) fn retarget_tracked(
+        &mut self,
+        a: EntityId,
+        old_b: EntityId,
+        new_b: EntityId,
+        current: TrackingTimestamp,
+    ) -> Result<bool, InsertError> {
+        let Some(relation) = self.graph.edge_weight(a, old_b) else {
+            return Ok(false);
+        };
+
+        self.check_on_insert(a, new_b, relation)?;
+        self.delete_stale_node(new_b, current);
+
+        let relation = self.graph.remove_edge(a, old_b).unwrap();
+        if let Some(on_delete) = &self.hooks.on_delete {
+            on_delete(a, old_b, &relation);
+        }
+
+        self.touch(a);
+        self.touch(old_b);
+        self.touch(new_b);
+
+        let insertion = if R::TRACKING.tracks_insertion() {
+            Self::untrack(&mut self.retargeting_data, &Self::key(a, old_b));
+            Self::untrack(&mut self.insertion_data, &Self::key(a, old_b))
+        } else {
+            None
+        };
+
+        if R::Mode::is_exclusive_incoming() {
+            for e in self.neighbors(new_b, petgraph::Direction::Incoming) {
+                self.delete_edge_tracked(e, new_b, current);
+            }
+        }
+
+        // a relation that is overwritten counts as deleted
+        self.delete_edge_tracked(a, new_b, current);
+
+        self.graph.add_edge(a, new_b, relation);
+
+        self.classify(a);
+        self.classify(old_b);
+        self.classify(new_b);
+
+        if R::TRACKING.tracks_insertion() {
+            // the insertion was already seen, it keeps its sequence
+            if let Some(insertion) = insertion {
+                Self::track(&mut self.insertion_data, Self::key(a, new_b), insertion);
+            }
+            let sequence = self.next_sequence(current);
+            Self::track(
+                &mut self.retargeting_data,
+                Self::key(a, new_b),
+                (current, sequence, a, old_b),
+            );
+        }
+
+        Ok(true)
+    }
 }
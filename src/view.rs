@@ -48,6 +48,23 @@ where
     }
 }
 
+impl<'a, R> RelationView<'a, R>
+where
+    R: Relation,
+{
+    /// View over a storage that isn't part of a `World`, seeing all its tracking data.
+    pub(crate) fn detached(storage: &'a RelationStorage<R>, current: TrackingTimestamp) -> Self {
+        RelationView {
+            storage,
+            _borrow: None,
+            _all_borrow: None,
+            last_insertion: current.furthest_from(),
+            last_deletion: current.furthest_from(),
+            current,
+        }
+    }
+}
+
 unsafe impl<R> BorrowInfo for RelationView<'_, R>
 where
     R: Relation,
@@ -1,16 +1,32 @@
 use std::{
     any::{type_name, TypeId},
+    collections::HashMap,
     error::Error,
     fmt::Formatter,
 };
 
-use petgraph::{
-    algo::{is_cyclic_directed, is_cyclic_undirected},
-    EdgeType,
-};
+use petgraph::EdgeType;
 use shipyard::*;
 
-use crate::{relation_mode::RelationMode, storage::RelationStorage, GetRelation, Relation};
+use crate::{
+    adjacency::Adjacency,
+    components::RelationComponents,
+    relation_mode::RelationMode,
+    storage::{RelationStorage, StagedRelation},
+    GetRelation, Relation, RelationView, SelfLoops,
+};
+
+/// System updating the components synced with the relations `R`, see `Relation::MARKERS`.
+///
+/// Deleting an entity from the `World` can't reach the components of the entities it was related to,
+/// run this system after deleting entities and before reading these components.
+/// Borrowing or dropping a `RelationViewMut<R>` does the same.
+pub fn sync_relation_components<R>(mut relation_view: RelationViewMut<'_, R>)
+where
+    R: Relation,
+{
+    relation_view.sync_components();
+}
 
 /// Exclusive view over a relation storage.
 pub struct RelationViewMut<'a, R>
@@ -18,6 +34,7 @@ where
     R: Relation,
 {
     pub(crate) storage: &'a mut RelationStorage<R>,
+    components: RelationComponents<'a, R>,
     _borrow: Option<ExclusiveBorrow<'a>>,
     _all_borrow: Option<SharedBorrow<'a>>,
     last_insertion: TrackingTimestamp,
@@ -43,17 +60,28 @@ where
 
         let (storage, borrow) = unsafe { ARefMut::destructure(view) };
 
+        storage.apply_retention(current);
+
         let last_insertion = last_run.unwrap_or(storage.last_insert);
         let last_deletion = last_run.unwrap_or(current.furthest_from());
 
-        Ok(RelationViewMut {
+        let components =
+            RelationComponents::borrow(all_storages, all_borrow.clone(), last_run, current)?;
+
+        let mut view = RelationViewMut {
             storage,
+            components,
             _borrow: Some(borrow),
             _all_borrow: all_borrow,
             last_insertion,
             last_deletion,
             current,
-        })
+        };
+
+        // entities can be deleted without a view
+        view.sync_components();
+
+        Ok(view)
     }
 }
 
@@ -68,6 +96,8 @@ where
             storage_id: TypeId::of::<RelationStorage<R>>().into(),
             thread_safe: true,
         });
+
+        RelationComponents::<R>::borrow_info(info);
     }
 
     fn enable_tracking(
@@ -76,6 +106,15 @@ where
     }
 }
 
+impl<R> Drop for RelationViewMut<'_, R>
+where
+    R: Relation,
+{
+    fn drop(&mut self) {
+        self.sync_components();
+    }
+}
+
 impl<'a, R> Delete for RelationViewMut<'a, R>
 where
     R: Relation,
@@ -86,9 +125,23 @@ where
 }
 
 /// Error returned by `RelationViewMut::insert`.
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum InsertError {
-    CycleDetected,
+    /// The relation between `a` and `b` would close a cycle.
+    ///
+    /// `path` goes from `b` back to `a` through the existing relations, both included.
+    CycleDetected {
+        a: EntityId,
+        b: EntityId,
+        path: Vec<EntityId>,
+    },
+    Rejected,
+    SelfLoop,
+    MaxOutgoingExceeded,
+    MaxIncomingExceeded,
+    SymmetricDuplicate,
+    Invalid(&'static str),
 }
 
 impl Error for InsertError {}
@@ -96,9 +149,24 @@ impl Error for InsertError {}
 impl core::fmt::Debug for InsertError {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), core::fmt::Error> {
         match self {
-            InsertError::CycleDetected => {
-                f.write_str("Insertion would cause a cycle while `RelationMode` is set to acyclic.")
+            InsertError::CycleDetected { a, b, path } => write!(
+                f,
+                "Insertion of {a:?} -> {b:?} would cause a cycle through {path:?} while `RelationMode` is set to acyclic."
+            ),
+            InsertError::Rejected => f.write_str("Insertion was rejected by an `on_insert` hook."),
+            InsertError::SelfLoop => {
+                f.write_str("Insertion would relate an entity to itself while `SELF_LOOPS` is set to reject.")
+            }
+            InsertError::MaxOutgoingExceeded => {
+                f.write_str("Insertion would exceed `MAX_OUTGOING` relations for an entity.")
             }
+            InsertError::MaxIncomingExceeded => {
+                f.write_str("Insertion would exceed `MAX_INCOMING` relations for an entity.")
+            }
+            InsertError::SymmetricDuplicate => f.write_str(
+                "Insertion would duplicate a relation in the opposite direction while `SYMMETRIC_UNIQUE` is set.",
+            ),
+            InsertError::Invalid(reason) => write!(f, "Insertion failed validation: {reason}"),
         }
     }
 }
@@ -123,63 +191,537 @@ where
         b: EntityId,
         relation: R,
     ) -> Result<(), InsertError> {
-        if R::Mode::is_exclusive_incoming() {
-            for e in self
-                .storage
-                .graph
-                .neighbors_directed(b, petgraph::Direction::Incoming)
-                .collect::<Vec<_>>()
-            {
-                self.storage.delete_edge_tracked(e, b, self.current);
-            }
+        if self.storage.check_insert(&*self, a, b, &relation)? {
+            self.storage.check_on_insert(a, b, &relation)?;
+            self.storage.insert_tracked(a, b, relation, self.current);
         }
-        if R::Mode::is_exclusive_outgoing() {
-            for e in self
-                .storage
-                .graph
-                .neighbors_directed(a, petgraph::Direction::Outgoing)
-                .collect::<Vec<_>>()
-            {
-                self.storage.delete_edge_tracked(a, e, self.current);
+
+        Ok(())
+    }
+
+    /// Runs the checks of `insert_checked` for a relation between `a` and `b`, without inserting it.
+    ///
+    /// The `on_insert` hook isn't called, it only sees relations that are about to be inserted.
+    pub fn check_insert(&self, a: EntityId, b: EntityId, relation: &R) -> Result<(), InsertError> {
+        self.storage.check_insert(self, a, b, relation).map(|_| ())
+    }
+
+    /// Checks a relation of a bundle and stages it, see `RelationStorage::stage`.
+    pub(crate) fn stage(
+        &mut self,
+        a: EntityId,
+        b: EntityId,
+        relation: R,
+    ) -> Result<StagedRelation<R>, InsertError> {
+        let accepted = self.storage.check_insert(&*self, a, b, &relation)?;
+
+        Ok(self.storage.stage(a, b, relation, accepted))
+    }
+
+    pub(crate) fn unstage(&mut self, staged: &mut StagedRelation<R>) {
+        self.storage.unstage(staged);
+    }
+
+    pub(crate) fn insert_staged(&mut self, staged: StagedRelation<R>) {
+        self.storage.insert_staged(staged, self.current);
+    }
+
+    /// Moves the relation between `a` and `old_b` to `a` and `new_b`, keeping its data.
+    ///
+    /// The move shows up in `retargeted` instead of `deleted` and `inserted`,
+    /// but goes through `on_delete` for `old_b` and can be rejected by `on_insert` for `new_b`.
+    /// Returns `Ok(false)` if there is no relation between `a` and `old_b`,
+    /// or if the move is dropped because of `Relation::SELF_LOOPS`.
+    pub fn retarget(
+        &mut self,
+        a: EntityId,
+        old_b: EntityId,
+        new_b: EntityId,
+    ) -> Result<bool, InsertError> {
+        if !self.storage.graph.contains_edge(a, old_b) {
+            return Ok(false);
+        }
+
+        if old_b == new_b {
+            return Ok(true);
+        }
+
+        if a == new_b {
+            match R::SELF_LOOPS {
+                _ if R::Mode::is_acyclic() => return Err(InsertError::SelfLoop),
+                SelfLoops::Allow => {}
+                SelfLoops::Reject => return Err(InsertError::SelfLoop),
+                SelfLoops::Ignore => return Ok(false),
             }
         }
 
-        self.storage.insert_tracked(a, b, relation, self.current);
+        let relation = self.storage.graph.edge_weight(a, old_b).unwrap();
+        self.storage
+            .check_constraints(&*self, a, new_b, relation, true)?;
 
-        let mut result = Ok(());
+        if RelationStorage::<R>::is_acyclic() && a != new_b {
+            let path = if <R::Mode as RelationMode>::EdgeType::is_directed() {
+                self.storage.path(new_b, a)
+            } else {
+                // the relation being moved doesn't connect `a` and `new_b` anymore
+                self.storage.path_through(new_b, a, |from, to| {
+                    (from, to) != (a, old_b) && (from, to) != (old_b, a)
+                })
+            };
 
-        if R::ACYCLIC {
-            if <R::Mode as RelationMode>::EdgeType::is_directed() {
-                if is_cyclic_directed(&self.storage.graph) {
-                    result = Err(InsertError::CycleDetected);
-                }
-            } else if is_cyclic_undirected(&self.storage.graph) {
-                result = Err(InsertError::CycleDetected);
+            if let Some(path) = path {
+                return Err(InsertError::CycleDetected { a, b: new_b, path });
             }
         }
 
-        if result.is_err() {
-            self.storage.graph.remove_edge(a, b); // do not track here
-        }
+        self.storage.retarget_tracked(a, old_b, new_b, self.current)
+    }
 
-        result
+    /// See `RelationStorage::on_insert`.
+    pub fn on_insert<F>(&mut self, f: F)
+    where
+        F: Fn(EntityId, EntityId, &R) -> bool + Send + Sync + 'static,
+    {
+        self.storage.on_insert(f);
+    }
+
+    /// See `RelationStorage::on_delete`.
+    pub fn on_delete<F>(&mut self, f: F)
+    where
+        F: Fn(EntityId, EntityId, &R) + Send + Sync + 'static,
+    {
+        self.storage.on_delete(f);
+    }
+
+    /// See `RelationStorage::on_replace`.
+    pub fn on_replace<F>(&mut self, f: F)
+    where
+        F: Fn(EntityId, EntityId, &R) + Send + Sync + 'static,
+    {
+        self.storage.on_replace(f);
     }
 
     pub fn delete_relation(&mut self, a: EntityId, b: EntityId) -> bool {
         self.storage.delete_edge_tracked(a, b, self.current)
     }
 
+    /// Deletes all relations of `e`, each deletion is tracked.
     pub fn delete_relations_with(&mut self, e: EntityId) -> bool {
-        self.storage.graph.remove_node(e)
+        self.storage.delete_node_tracked(e, self.current)
+    }
+
+    /// Updates the components synced with the relations, see `Relation::MARKERS` and `Relation::MIRROR`.
+    ///
+    /// Done automatically when the view is borrowed and dropped.
+    pub fn sync_components(&mut self) {
+        self.components.sync(self.storage);
+    }
+
+    /// Deletes all relations, each deletion is tracked.
+    pub fn clear(&mut self) {
+        self.storage.delete_all_tracked(self.current);
+    }
+
+    /// Keeps only the relations for which `f` returns `true`, each deletion is tracked.
+    ///
+    /// Undirected relations are visited once, with the smaller `EntityId` first.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(EntityId, EntityId, &mut R) -> bool,
+    {
+        for (a, b) in self.storage.pairs() {
+            let keep = match self.storage.graph.edge_weight_mut(a, b) {
+                Some(relation) => f(a, b, relation),
+                None => true,
+            };
+
+            if !keep {
+                self.storage.delete_edge_tracked(a, b, self.current);
+            }
+        }
+    }
+
+    /// Moves the relations of the entities in `mapping` into `other`, usually a view of another `World`.
+    ///
+    /// Relations between two moved entities are inserted into `other` between their new ids,
+    /// the entities' relations with entities that aren't moved are deleted.
+    /// Moved relations don't show up as deleted in this view.
+    ///
+    /// Every relation is checked against `other` before any of them is moved.
+    /// If `other` rejects one, the error is returned and both views are left as they were.
+    pub fn move_relations_to(
+        &mut self,
+        other: &mut RelationViewMut<'_, R>,
+        mapping: &HashMap<EntityId, EntityId>,
+    ) -> Result<(), InsertError> {
+        let directed = <R::Mode as RelationMode>::EdgeType::is_directed();
+
+        let mut entities = mapping.keys().copied().collect::<Vec<_>>();
+        entities.sort_unstable();
+
+        // relations are staged in `other` so that each check sees the previous ones
+        let mut staged = Vec::new();
+        let mut result = Ok(());
+        'stage: for &a in &entities {
+            for b in self.storage.neighbors(a, petgraph::Direction::Outgoing) {
+                if !(directed || a <= b) {
+                    continue;
+                }
+
+                let Some(&new_b) = mapping.get(&b) else {
+                    continue;
+                };
+                let new_a = mapping[&a];
+                let relation = self.storage.graph.edge_weight(a, b).unwrap();
+
+                match other.storage.check_insert(&*other, new_a, new_b, relation) {
+                    Ok(true) => {
+                        let relation = self.storage.graph.remove_edge(a, b).unwrap();
+                        staged.push((a, b, other.storage.stage(new_a, new_b, relation, true)));
+                    }
+                    Ok(false) => {}
+                    Err(err) => {
+                        result = Err(err);
+                        break 'stage;
+                    }
+                }
+            }
+        }
+
+        for (_, _, relation) in staged.iter_mut().rev() {
+            other.storage.unstage(relation);
+        }
+
+        let result = result.and_then(|()| {
+            staged
+                .iter()
+                .try_for_each(|(_, _, relation)| other.storage.check_on_insert_staged(relation))
+        });
+
+        if let Err(err) = result {
+            for (a, b, relation) in staged.into_iter().rev() {
+                self.storage
+                    .graph
+                    .add_edge(a, b, relation.into_relation().unwrap());
+            }
+
+            return Err(err);
+        }
+
+        for (a, b, relation) in staged {
+            other.storage.insert_staged(relation, other.current);
+            self.storage.forget_edge(a, b);
+        }
+
+        for entity in entities {
+            self.storage.delete_node_tracked(entity, self.current);
+        }
+
+        Ok(())
     }
 
     pub fn clear_deleted(&mut self) {
-        self.storage.deletion_data.clear();
+        self.storage.clear_deleted();
+    }
+
+    /// Clears insertion and retargeting tracking data, see `RelationStorage::clear_inserted`.
+    pub fn clear_inserted(&mut self) {
+        self.storage.clear_inserted(self.current);
     }
 
     pub fn relation_mut(&mut self, a: EntityId, b: EntityId) -> Option<&mut R> {
         self.storage.graph.edge_weight_mut(a, b)
     }
+
+    pub fn get_mut(
+        &mut self,
+        entity: EntityId,
+    ) -> <R::Mode as RelationMode>::GetOutgoingMut<'_, R> {
+        self.get_outgoing_mut(entity)
+    }
+
+    /// Mutable version of `get_incoming`.
+    ///
+    /// When the mode isn't exclusive on the incoming side, the default `GraphMap` storage has to scan
+    /// all relations to find the ones of `entity`. `SparseSet<Mode>` storages only visit the relations
+    /// of `entity`, and so does `for_each_incoming_mut` with any storage.
+    pub fn get_incoming_mut(
+        &mut self,
+        entity: EntityId,
+    ) -> <R::Mode as RelationMode>::GetIncomingMut<'_, R> {
+        <R::Mode as RelationMode>::get_incoming_mut(&mut self.storage.graph, entity)
+    }
+
+    /// Mutable version of `get_outgoing`.
+    ///
+    /// When the mode isn't exclusive on the outgoing side, the default `GraphMap` storage has to scan
+    /// all relations to find the ones of `entity`. `SparseSet<Mode>` storages only visit the relations
+    /// of `entity`, and so does `for_each_outgoing_mut` with any storage.
+    pub fn get_outgoing_mut(
+        &mut self,
+        entity: EntityId,
+    ) -> <R::Mode as RelationMode>::GetOutgoingMut<'_, R> {
+        <R::Mode as RelationMode>::get_outgoing_mut(&mut self.storage.graph, entity)
+    }
+
+    /// Calls `f` with the entity on the other side and the data of each incoming relation of `entity`.
+    ///
+    /// Each relation is looked up by its ends, one at a time.
+    pub fn for_each_incoming_mut<F>(&mut self, entity: EntityId, mut f: F)
+    where
+        F: FnMut(EntityId, &mut R),
+    {
+        for a in self
+            .storage
+            .neighbors(entity, petgraph::Direction::Incoming)
+        {
+            if let Some(relation) = self.storage.graph.edge_weight_mut(a, entity) {
+                f(a, relation);
+            }
+        }
+    }
+
+    /// Calls `f` with the entity on the other side and the data of each outgoing relation of `entity`.
+    ///
+    /// Each relation is looked up by its ends, one at a time.
+    pub fn for_each_outgoing_mut<F>(&mut self, entity: EntityId, mut f: F)
+    where
+        F: FnMut(EntityId, &mut R),
+    {
+        for b in self
+            .storage
+            .neighbors(entity, petgraph::Direction::Outgoing)
+        {
+            if let Some(relation) = self.storage.graph.edge_weight_mut(entity, b) {
+                f(b, relation);
+            }
+        }
+    }
+}
+
+/// Selects which relations of an entity are copied by `RelationViewMut::copy_relations`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CopyDirection {
+    Outgoing,
+    Incoming,
+    Both,
+}
+
+impl<'a, R> RelationViewMut<'a, R>
+where
+    R: Relation + Clone,
+{
+    /// Copies the relations of `from` onto `to`.
+    ///
+    /// Relations `from` has with itself become relations of `to` with itself.
+    /// Exclusive modes apply as usual, so a copied relation can replace an existing relation of `to`.
+    /// Copies that would take a relation from `from` are skipped: its outgoing relations with
+    /// `is_exclusive_incoming` and its incoming ones with `is_exclusive_outgoing`.
+    ///
+    /// All copies are checked before any of them is inserted,
+    /// if one of them is rejected the error is returned and the relations are left as they were.
+    pub fn copy_relations(
+        &mut self,
+        from: EntityId,
+        to: EntityId,
+        direction: CopyDirection,
+    ) -> Result<(), InsertError> {
+        let remap = |e: EntityId| if e == from { to } else { e };
+        let mut relations = Vec::new();
+
+        if direction != CopyDirection::Incoming {
+            relations.extend(
+                self.storage
+                    .graph
+                    .edges_directed(from, petgraph::Direction::Outgoing)
+                    .filter(|&(b, _)| b == from || !R::Mode::is_exclusive_incoming())
+                    .map(|(b, r)| (to, remap(b), r.clone())),
+            );
+        }
+
+        // undirected relations are the same in both directions
+        if direction == CopyDirection::Incoming
+            || (direction == CopyDirection::Both
+                && <R::Mode as RelationMode>::EdgeType::is_directed())
+        {
+            relations.extend(
+                self.storage
+                    .graph
+                    .edges_directed(from, petgraph::Direction::Incoming)
+                    .filter(|&(a, _)| a == from || !R::Mode::is_exclusive_outgoing())
+                    .map(|(a, r)| (remap(a), to, r.clone())),
+            );
+        }
+
+        // copies are staged so that each check sees the previous ones
+        let mut staged = Vec::new();
+        let mut result = Ok(());
+        for (a, b, relation) in relations {
+            match self.stage(a, b, relation) {
+                Ok(relation) => staged.push(relation),
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+
+        for relation in staged.iter_mut().rev() {
+            self.unstage(relation);
+        }
+
+        result?;
+        for relation in &staged {
+            self.storage.check_on_insert_staged(relation)?;
+        }
+
+        for relation in staged {
+            self.insert_staged(relation);
+        }
+
+        Ok(())
+    }
+
+    /// Duplicates `root`, its `descendants` and their relations.
+    ///
+    /// Relations between duplicated entities are remapped onto the new entities,
+    /// relations with entities outside of the subtree are kept and copied as they are.
+    /// In exclusive modes, copies that would replace a relation of an outside entity are skipped:
+    /// its incoming relations with `is_exclusive_outgoing` and its outgoing ones with `is_exclusive_incoming`.
+    /// Returns a map from each original entity to its duplicate.
+    ///
+    /// If a copy is rejected, the new entities and the copies already inserted are deleted
+    /// and the error is returned.
+    pub fn clone_subtree(
+        &mut self,
+        entities: &mut EntitiesViewMut<'_>,
+        root: EntityId,
+    ) -> Result<HashMap<EntityId, EntityId>, InsertError> {
+        let directed = <R::Mode as RelationMode>::EdgeType::is_directed();
+
+        let nodes = std::iter::once(root)
+            .chain(self.descendants(root))
+            .collect::<Vec<_>>();
+        let mapping = nodes
+            .iter()
+            .map(|&e| (e, entities.add_entity((), ())))
+            .collect::<HashMap<_, _>>();
+
+        let mut relations = Vec::new();
+
+        for &node in &nodes {
+            for (other, r) in self
+                .storage
+                .graph
+                .edges_directed(node, petgraph::Direction::Outgoing)
+            {
+                match mapping.get(&other) {
+                    // undirected relations between two nodes of the subtree are visited twice
+                    Some(&clone) => {
+                        if directed || node <= other {
+                            relations.push((mapping[&node], clone, r.clone()));
+                        }
+                    }
+                    None => {
+                        if !R::Mode::is_exclusive_incoming() {
+                            relations.push((mapping[&node], other, r.clone()));
+                        }
+                    }
+                }
+            }
+
+            if directed && !R::Mode::is_exclusive_outgoing() {
+                for (other, r) in self
+                    .storage
+                    .graph
+                    .edges_directed(node, petgraph::Direction::Incoming)
+                {
+                    if !mapping.contains_key(&other) {
+                        relations.push((other, mapping[&node], r.clone()));
+                    }
+                }
+            }
+        }
+
+        let mut inserted = Vec::new();
+
+        for (a, b, relation) in relations {
+            match self
+                .storage
+                .check_insert(&*self, a, b, &relation)
+                .and_then(|accepted| {
+                    if accepted {
+                        self.storage.check_on_insert(a, b, &relation)?;
+                    }
+
+                    Ok(accepted)
+                }) {
+                Ok(true) => {
+                    self.storage.insert_tracked(a, b, relation, self.current);
+                    inserted.push((a, b));
+                }
+                Ok(false) => {}
+                Err(err) => {
+                    // copies only involve new entities and can't replace anything, taking them back is enough
+                    for (a, b) in inserted {
+                        self.storage.take_edge(a, b);
+                    }
+
+                    for &clone in mapping.values() {
+                        self.storage.graph.remove_node(clone);
+                        self.storage.classify(clone);
+                        entities.delete_unchecked(clone);
+                    }
+
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(mapping)
+    }
+
+    /// Replaces all relations with copies of the relations of `source`,
+    /// usually a view of another `World` with the same entities.
+    ///
+    /// Current relations are deleted and copies are inserted, both are tracked.
+    /// The copies are checked and go through `on_insert` before anything is deleted,
+    /// if one of them is rejected the error is returned and the relations are left as they were.
+    pub fn clone_relations_from<G>(&mut self, source: &G) -> Result<(), InsertError>
+    where
+        G: GetRelation<R>,
+    {
+        let source = source.storage();
+
+        // the copies are checked in an empty storage, as they would be inserted after `clear`
+        let mut staging = RelationStorage::new(self.current);
+        let mut relations = Vec::new();
+
+        for (a, b) in source.pairs() {
+            if let Some(relation) = source.graph.edge_weight(a, b) {
+                let view = RelationView::detached(&staging, self.current);
+                let accepted = staging.check_insert(&view, a, b, relation)?;
+                drop(view);
+
+                if accepted {
+                    staging.insert_tracked(a, b, relation.clone(), self.current);
+                    relations.push((a, b, relation.clone()));
+                }
+            }
+        }
+
+        for (a, b, relation) in &relations {
+            self.storage.run_on_insert(*a, *b, relation)?;
+        }
+
+        self.clear();
+
+        for (a, b, relation) in relations {
+            self.storage.insert_tracked(a, b, relation, self.current);
+        }
+
+        Ok(())
+    }
 }
 
 impl<R> GetRelation<R> for RelationViewMut<'_, R>
@@ -0,0 +1,155 @@
+use std::collections::HashSet;
+
+use petgraph::{
+    visit::{
+        Data, GraphBase, GraphRef, IntoEdgeReferences, IntoEdges, IntoNeighbors,
+        IntoNeighborsDirected, IntoNodeIdentifiers, NodeCount, Visitable,
+    },
+    EdgeType,
+};
+use shipyard::EntityId;
+
+use crate::{
+    adjacency::{Adjacency, WalkNeighbors},
+    relation_mode::RelationMode,
+    Relation, RelationView, RelationViewMut,
+};
+
+/// Implements petgraph's visit traits on shared references to a view,
+/// so petgraph algorithms can run directly on relations.
+macro_rules! impl_visit {
+    ($view: ident) => {
+        impl<R> GraphBase for &$view<'_, R>
+        where
+            R: Relation,
+        {
+            type NodeId = EntityId;
+            type EdgeId = (EntityId, EntityId);
+        }
+
+        impl<R> GraphRef for &$view<'_, R> where R: Relation {}
+
+        impl<R> Data for &$view<'_, R>
+        where
+            R: Relation,
+        {
+            type NodeWeight = ();
+            type EdgeWeight = R;
+        }
+
+        impl<'a, R> IntoNeighbors for &'a $view<'_, R>
+        where
+            R: Relation,
+        {
+            type Neighbors = WalkNeighbors<
+                <<R::Mode as RelationMode>::Adjacency<R> as Adjacency<R>>::Neighbors<'a>,
+            >;
+
+            fn neighbors(self, entity: EntityId) -> Self::Neighbors {
+                self.neighbors_directed(entity, petgraph::Direction::Outgoing)
+            }
+        }
+
+        impl<'a, R> IntoNeighborsDirected for &'a $view<'_, R>
+        where
+            R: Relation,
+        {
+            type NeighborsDirected = WalkNeighbors<
+                <<R::Mode as RelationMode>::Adjacency<R> as Adjacency<R>>::Neighbors<'a>,
+            >;
+
+            fn neighbors_directed(
+                self,
+                entity: EntityId,
+                direction: petgraph::Direction,
+            ) -> Self::NeighborsDirected {
+                WalkNeighbors::new(
+                    self.storage.graph.neighbors_directed(entity, direction),
+                    R::DETERMINISTIC,
+                )
+            }
+        }
+
+        impl<'a, R> IntoNodeIdentifiers for &'a $view<'_, R>
+        where
+            R: Relation,
+        {
+            type NodeIdentifiers =
+                WalkNeighbors<<<R::Mode as RelationMode>::Adjacency<R> as Adjacency<R>>::Nodes<'a>>;
+
+            fn node_identifiers(self) -> Self::NodeIdentifiers {
+                WalkNeighbors::new(self.storage.graph.nodes(), R::DETERMINISTIC)
+            }
+        }
+
+        impl<'a, R> IntoEdgeReferences for &'a $view<'_, R>
+        where
+            R: Relation,
+        {
+            type EdgeRef = (EntityId, EntityId, &'a R);
+            type EdgeReferences = Box<dyn Iterator<Item = (EntityId, EntityId, &'a R)> + 'a>;
+
+            /// Undirected relations are yielded once, with the smaller `EntityId` first.
+            fn edge_references(self) -> Self::EdgeReferences {
+                let graph = &self.storage.graph;
+                let directed = <R::Mode as RelationMode>::EdgeType::is_directed();
+
+                Box::new(self.node_identifiers().flat_map(move |a| {
+                    graph
+                        .edges_directed(a, petgraph::Direction::Outgoing)
+                        .filter(move |(b, _)| directed || a <= *b)
+                        .map(move |(b, relation)| (a, b, relation))
+                }))
+            }
+        }
+
+        impl<'a, R> IntoEdges for &'a $view<'_, R>
+        where
+            R: Relation,
+        {
+            type Edges = Box<dyn Iterator<Item = (EntityId, EntityId, &'a R)> + 'a>;
+
+            fn edges(self, a: EntityId) -> Self::Edges {
+                let mut edges = self
+                    .storage
+                    .graph
+                    .edges_directed(a, petgraph::Direction::Outgoing)
+                    .map(move |(b, relation)| (a, b, relation))
+                    .collect::<Vec<_>>();
+
+                if R::DETERMINISTIC {
+                    edges.sort_unstable_by_key(|(_, b, _)| *b);
+                }
+
+                Box::new(edges.into_iter())
+            }
+        }
+
+        impl<R> NodeCount for &$view<'_, R>
+        where
+            R: Relation,
+        {
+            fn node_count(&self) -> usize {
+                self.storage.graph.node_count()
+            }
+        }
+
+        impl<R> Visitable for &$view<'_, R>
+        where
+            R: Relation,
+        {
+            type Map = HashSet<EntityId>;
+
+            fn visit_map(&self) -> Self::Map {
+                HashSet::with_capacity(self.storage.graph.node_count())
+            }
+
+            fn reset_map(&self, map: &mut Self::Map) {
+                map.clear();
+            }
+        }
+    };
+}
+
+impl_visit!(RelationView);
+impl_visit!(RelationViewMut);